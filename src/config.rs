@@ -1,6 +1,8 @@
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct BotConfig {
     pub command_char: String,
+    /// whether commands no module handles are answered, rather than only logged
+    pub unknown_command_reply: Option<bool>,
 }
 
 config!(BotConfig, "config.toml");
@@ -0,0 +1,112 @@
+//! Benchmarks of learning and generating on a synthetic corpus
+
+use super::Args;
+use modules::{Dictionary, Quality, Sampling, Successors, DEFAULT_ORDER};
+use rand::{Rng, SeedableRng, StdRng};
+use std::env;
+use std::fs;
+use std::time::Instant;
+
+/// A random word index, the lower ones being much more frequent, like in natural language
+fn zipf_index<R: Rng>(rng: &mut R, num_words: usize) -> usize {
+    let x = (num_words as f64).powf(rng.gen::<f64>());
+    (x as usize).max(1).min(num_words) - 1
+}
+
+/// Runs `f` `count` times, returns the average time in nanoseconds
+fn time<F: FnMut(usize)>(count: usize, mut f: F) -> f64 {
+    let start = Instant::now();
+    for i in 0..count {
+        f(i);
+    }
+    let elapsed = start.elapsed();
+    (elapsed.as_secs() as f64 * 1e9 + elapsed.subsec_nanos() as f64) / count as f64
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args)?;
+    if !args.positional.is_empty() {
+        return Err(super::USAGE.to_owned());
+    }
+    let num_lines = args.get_parsed("lines")?.unwrap_or(200_000);
+    let num_words = args.get_parsed("words")?.unwrap_or(50_000);
+    let samples = args.get_parsed("samples")?.unwrap_or(1_000_000);
+    let sentences = args.get_parsed("sentences")?.unwrap_or(10_000);
+    if num_words == 0 || samples == 0 || sentences == 0 {
+        return Err("The numbers must be positive".to_owned());
+    }
+    let mut rng = StdRng::from_seed([0; 32]);
+
+    println!("Choosing the next word, average time:");
+    println!("{:>12}  {:>12}  {:>12}", "successors", "linear", "weighted");
+    let mut size = 10;
+    while size <= num_words {
+        let counts = (0..size as u32)
+            .map(|word| (word, (size as u32 / (word + 1)).max(1)))
+            .collect();
+        let successors = Successors::from_sorted(counts);
+        let total = successors.total();
+        // walking all the successors gets slow, so fewer samples are taken from the big ones
+        let count = (samples / size * 100).max(1000).min(samples);
+        let values: Vec<u64> = (0..count).map(|_| rng.gen_range(0, total)).collect();
+        let (mut linear_sum, mut weighted_sum) = (0u64, 0u64);
+        let linear = time(count, |i| {
+            linear_sum += successors.sample_linear(values[i]) as u64;
+        });
+        let weighted = time(count, |i| {
+            weighted_sum += successors.sample(values[i]) as u64;
+        });
+        if linear_sum != weighted_sum {
+            return Err("The samplers chose different words".to_owned());
+        }
+        println!("{:>12}  {:>9.0} ns  {:>9.0} ns", size, linear, weighted);
+        size *= 10;
+    }
+
+    println!(
+        "Synthetic corpus: {} lines, {} different words",
+        num_lines, num_words
+    );
+    let lines: Vec<String> = (0..num_lines)
+        .map(|_| {
+            let len = rng.gen_range(1, 16);
+            let words: Vec<String> = (0..len)
+                .map(|_| format!("w{}", zipf_index(&mut rng, num_words)))
+                .collect();
+            words.join(" ")
+        })
+        .collect();
+    // accept everything, so that only the generation itself is measured
+    let quality = Quality {
+        min_words: 0,
+        max_words: usize::max_value(),
+        retries: 0,
+        reject_copies: false,
+    };
+    let sampling = Sampling::new();
+    let mut dict = Dictionary::new(DEFAULT_ORDER);
+    let learning = time(lines.len(), |i| dict.learn_from_line(&lines[i]));
+    println!(
+        "Learning: {:.0} ns per line, {} entries",
+        learning,
+        dict.num_entries()
+    );
+    let generating = time(sentences, |_| {
+        dict.generate(None, &quality, &sampling, &mut rng);
+    });
+    println!("Generating in memory: {:.0} ns per sentence", generating);
+
+    let path = env::temp_dir().join(format!("chatbot-bench-{}.dat", ::std::process::id()));
+    let path_str = path.to_string_lossy().into_owned();
+    let result = super::save(&mut dict, &path_str).and_then(|_| super::load(&path_str));
+    let _ = fs::remove_file(&path);
+    let dict = result?;
+    let generating = time(sentences, |_| {
+        dict.generate(None, &quality, &sampling, &mut rng);
+    });
+    println!(
+        "Generating from the file: {:.0} ns per sentence",
+        generating
+    );
+    Ok(())
+}
@@ -0,0 +1,334 @@
+//! Learning from text files, IRC logs and chat exports
+
+use super::{load_or_create, save, Args};
+use modules::{tokenizer, Dictionary};
+use regex::Regex;
+use serde_json;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    /// One sentence per line
+    Text,
+    /// irssi logs: `12:34 <@nick> message`
+    Irssi,
+    /// WeeChat logs: `2018-01-01 12:34:56<TAB>@nick<TAB>message`
+    Weechat,
+    /// Slack workspace export: a directory per channel, a JSON file per day
+    Slack,
+    /// DiscordChatExporter JSON
+    Discord,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "text" => Some(Format::Text),
+            "irssi" => Some(Format::Irssi),
+            "weechat" => Some(Format::Weechat),
+            "slack" => Some(Format::Slack),
+            "discord" => Some(Format::Discord),
+            _ => None,
+        }
+    }
+
+    fn is_json(&self) -> bool {
+        *self == Format::Slack || *self == Format::Discord
+    }
+}
+
+#[derive(Deserialize)]
+struct SlackProfile {
+    name: Option<String>,
+    display_name: Option<String>,
+    real_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SlackMessage {
+    subtype: Option<String>,
+    user: Option<String>,
+    username: Option<String>,
+    user_profile: Option<SlackProfile>,
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DiscordAuthor {
+    name: String,
+    nickname: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DiscordMessage {
+    #[serde(rename = "type")]
+    message_type: Option<String>,
+    content: String,
+    author: DiscordAuthor,
+}
+
+#[derive(Deserialize)]
+struct DiscordExport {
+    messages: Vec<DiscordMessage>,
+}
+
+struct Importer {
+    dict: Dictionary,
+    format: Format,
+    line_regex: Option<Regex>,
+    /// lowercased
+    skip_nicks: Vec<String>,
+    learned: usize,
+    skipped: usize,
+    /// learned before, e.g. from overlapping logs
+    duplicates: usize,
+}
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args)?;
+    if args.positional.len() < 2 {
+        return Err(super::USAGE.to_owned());
+    }
+    let format = match args.get("format") {
+        Some(name) => Format::from_name(name).ok_or_else(|| format!("Unknown format: {}", name))?,
+        None => Format::Text,
+    };
+    let dict_path = &args.positional[0];
+    let mut dict = load_or_create(dict_path, args.get_parsed("order")?)?;
+    let tokenizer = args.get("tokenizer").unwrap_or("chat");
+    dict.set_tokenizer(
+        tokenizer::from_name(tokenizer)
+            .ok_or_else(|| format!("Unknown tokenizer: {}", tokenizer))?,
+    );
+
+    let mut importer = Importer::new(dict, format, args.get_all("skip-nick"));
+    for input in &args.positional[1..] {
+        importer.import_path(Path::new(input))?;
+    }
+    println!(
+        "Learned {} lines, skipped {} lines from ignored nicks and {} lines learned before",
+        importer.learned, importer.skipped, importer.duplicates
+    );
+    save(&mut importer.dict, dict_path)
+}
+
+impl Importer {
+    fn new(dict: Dictionary, format: Format, skip_nicks: &[String]) -> Importer {
+        Importer {
+            dict,
+            format,
+            line_regex: match format {
+                Format::Irssi => Some(Regex::new(r"^\S+ <[ @+%&~]?([^>]+)> (.*)$").unwrap()),
+                Format::Weechat => Some(Regex::new(r"^[^\t]+\t[@+%&~]?([^\t]+)\t(.*)$").unwrap()),
+                _ => None,
+            },
+            skip_nicks: skip_nicks.iter().map(|nick| nick.to_lowercase()).collect(),
+            learned: 0,
+            skipped: 0,
+            duplicates: 0,
+        }
+    }
+
+    fn import_path(&mut self, path: &Path) -> Result<(), String> {
+        if path.is_dir() {
+            let mut entries: Vec<PathBuf> = fs::read_dir(path)
+                .and_then(|entries| entries.map(|entry| entry.map(|e| e.path())).collect())
+                .map_err(|err| format!("Couldn't read {}: {}", path.display(), err))?;
+            // import in a stable order, which is also chronological for Slack's per-day files
+            entries.sort();
+            for entry in entries {
+                if entry.is_dir() {
+                    self.import_path(&entry)?;
+                } else if !self.format.is_json() || Importer::is_json_file(&entry) {
+                    // exports contain other files as well (users.json etc.), which can't be
+                    // imported, but shouldn't stop the import of the rest
+                    if let Err(err) = self.import_path(&entry) {
+                        eprintln!("Skipping: {}", err);
+                    }
+                }
+            }
+            Ok(())
+        } else {
+            let file = File::open(path)
+                .map_err(|err| format!("Couldn't open {}: {}", path.display(), err))?;
+            let reader = BufReader::new(file);
+            let result = match self.format {
+                Format::Slack => self.import_slack(reader),
+                Format::Discord => self.import_discord(reader),
+                _ => self.import_lines(reader),
+            };
+            result.map_err(|err| format!("Couldn't import {}: {}", path.display(), err))
+        }
+    }
+
+    fn is_json_file(path: &Path) -> bool {
+        path.extension().map_or(false, |ext| ext == "json")
+    }
+
+    fn import_lines<R: BufRead>(&mut self, mut reader: R) -> Result<(), String> {
+        let mut bytes = Vec::new();
+        loop {
+            bytes.clear();
+            if reader
+                .read_until(b'\n', &mut bytes)
+                .map_err(|err| err.to_string())?
+                == 0
+            {
+                return Ok(());
+            }
+            // old logs are not always valid UTF-8, salvage what we can
+            let line = String::from_utf8_lossy(&bytes);
+            let line = line.trim_end_matches(|c| c == '\r' || c == '\n');
+            let captures = match self.line_regex {
+                Some(ref regex) => regex
+                    .captures(line)
+                    .map(|captures| (captures[1].to_owned(), captures[2].to_owned())),
+                None => {
+                    self.learn(&[], line);
+                    continue;
+                }
+            };
+            if let Some((nick, text)) = captures {
+                // WeeChat puts joins, parts, actions etc. in the nick column as well
+                if nick.contains(char::is_whitespace)
+                    || ["-->", "<--", "--", "=!="].contains(&&nick[..])
+                {
+                    continue;
+                }
+                self.learn(&[&nick], &text);
+            }
+        }
+    }
+
+    fn import_slack<R: BufRead>(&mut self, reader: R) -> Result<(), String> {
+        let messages: Vec<SlackMessage> =
+            serde_json::from_reader(reader).map_err(|err| err.to_string())?;
+        for message in messages {
+            // joins, topic changes, bot messages etc.
+            if message.subtype.is_some() {
+                continue;
+            }
+            let text = match message.text {
+                Some(text) => text,
+                None => continue,
+            };
+            let mut authors = vec![message.user, message.username];
+            if let Some(profile) = message.user_profile {
+                authors.extend(vec![profile.name, profile.display_name, profile.real_name]);
+            }
+            let authors: Vec<&str> = authors
+                .iter()
+                .filter_map(|author| author.as_ref().map(String::as_str))
+                .collect();
+            self.learn(&authors, &text);
+        }
+        Ok(())
+    }
+
+    fn import_discord<R: BufRead>(&mut self, reader: R) -> Result<(), String> {
+        let export: DiscordExport =
+            serde_json::from_reader(reader).map_err(|err| err.to_string())?;
+        for message in export.messages {
+            // pins, joins, calls etc.
+            match message.message_type.as_ref().map(String::as_str) {
+                None | Some("Default") | Some("Reply") => (),
+                _ => continue,
+            }
+            let mut authors = vec![&message.author.name[..]];
+            if let Some(ref nickname) = message.author.nickname {
+                authors.push(nickname);
+            }
+            self.learn(&authors, &message.content);
+        }
+        Ok(())
+    }
+
+    /// `authors` are all the names the author of the line is known under
+    fn learn(&mut self, authors: &[&str], text: &str) {
+        if authors
+            .iter()
+            .any(|author| self.skip_nicks.contains(&author.to_lowercase()))
+        {
+            self.skipped += 1;
+            return;
+        }
+        for line in text.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            // so that importing the same logs again doesn't count the lines twice
+            if self.dict.has_learned(line) {
+                self.duplicates += 1;
+                continue;
+            }
+            self.dict.learn_from_line(line);
+            self.learned += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn import(format: Format, skip_nicks: &[&str], log: &str) -> Importer {
+        let mut dict = Dictionary::new(2);
+        dict.set_tokenizer(tokenizer::from_name("chat").unwrap());
+        let skip_nicks: Vec<String> = skip_nicks.iter().map(|&nick| nick.to_owned()).collect();
+        let mut importer = Importer::new(dict, format, &skip_nicks);
+        importer.import_lines(log.as_bytes()).unwrap();
+        importer
+    }
+
+    #[test]
+    fn irssi_messages_are_learned() {
+        let log = "--- Log opened Mon Jan 01 00:00:00 2018
+12:34 <@alice> hello there
+12:35  * alice waves at everyone
+12:36 -!- bob [~bob@example.com] has joined #chat
+12:37 -!- alice is now known as alicia
+12:38 < bob> hi alice
+12:39 <+carol> good morning
+12:40 <IrcBot> I am a bot
+";
+        let importer = import(Format::Irssi, &["ircbot"], log);
+        assert_eq!((importer.learned, importer.skipped), (3, 1));
+        for line in &["hello there", "hi alice", "good morning"] {
+            assert!(importer.dict.has_learned(line), "{}", line);
+        }
+        assert!(importer.dict.find_word("waves").is_none());
+        assert!(importer.dict.find_word("joined").is_none());
+    }
+
+    #[test]
+    fn weechat_messages_are_learned() {
+        let log = "2018-01-01 12:34:56\t@alice\thello there
+2018-01-01 12:35:00\t *\talice waves at everyone
+2018-01-01 12:36:00\t-->\tbob (~bob@example.com) has joined #chat
+2018-01-01 12:37:00\t<--\tbob (~bob@example.com) has quit
+2018-01-01 12:38:00\t--\talice is now known as alicia
+2018-01-01 12:39:00\t=!=\tirc: connection lost
+2018-01-01 12:40:00\t+carol\tgood morning
+2018-01-01 12:41:00\tIrcBot\tI am a bot
+";
+        let importer = import(Format::Weechat, &["IrcBot"], log);
+        assert_eq!((importer.learned, importer.skipped), (2, 1));
+        assert!(importer.dict.has_learned("hello there"));
+        assert!(importer.dict.has_learned("good morning"));
+        for word in &["waves", "joined", "quit", "known", "lost"] {
+            assert!(importer.dict.find_word(word).is_none(), "{}", word);
+        }
+    }
+
+    #[test]
+    fn lines_learned_before_are_skipped() {
+        let log = "hello there\nhello there\ngood morning\n\nHello  there\n";
+        let mut importer = import(Format::Text, &[], log);
+        assert_eq!((importer.learned, importer.duplicates), (2, 2));
+        // importing the same file again changes nothing
+        importer.import_lines(log.as_bytes()).unwrap();
+        assert_eq!((importer.learned, importer.duplicates), (2, 6));
+    }
+}
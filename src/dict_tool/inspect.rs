@@ -0,0 +1,119 @@
+//! Read-only commands showing what a dictionary contains
+
+use super::{load, parse_entry, Args};
+use modules::{Dictionary, Word};
+use regex::Regex;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+pub fn check(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args)?;
+    if args.positional.len() != 1 {
+        return Err(super::USAGE.to_owned());
+    }
+    load(&args.positional[0])?;
+    println!("{} is fine", args.positional[0]);
+    Ok(())
+}
+
+pub fn stats(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args)?;
+    if args.positional.len() != 1 {
+        return Err(super::USAGE.to_owned());
+    }
+    let top = args.get_parsed("top")?.unwrap_or(10);
+    let dict = load(&args.positional[0])?;
+
+    let (mut transitions, mut total) = (0, 0u64);
+    // the `top` most frequent transitions seen so far, the least frequent of them first
+    let mut most_frequent = BinaryHeap::new();
+    dict.for_each_transition(|entry, word, count| {
+        transitions += 1;
+        total += count as u64;
+        most_frequent.push(Reverse((count, entry.to_vec(), word)));
+        if most_frequent.len() > top {
+            most_frequent.pop();
+        }
+    });
+    println!("Order:       {}", dict.order());
+    println!("Words:       {}", dict.num_words());
+    println!("Entries:     {}", dict.num_entries());
+    println!("Transitions: {} (seen {} times)", transitions, total);
+    println!("Most frequent transitions:");
+    for Reverse((count, entry, word)) in most_frequent.into_sorted_vec() {
+        println!(
+            "{:>10}  {} -> {}",
+            count,
+            entry_str(&dict, &entry),
+            dict.word_str(word)
+        );
+    }
+    Ok(())
+}
+
+pub fn show(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args)?;
+    if args.positional.len() < 2 {
+        return Err(super::USAGE.to_owned());
+    }
+    let dict = load(&args.positional[0])?;
+    let entry = parse_entry(&dict, &args.positional[1..])?;
+
+    let successors = match dict.successors(&entry) {
+        Some(successors) => successors,
+        None => {
+            println!("No transitions from {}", entry_str(&dict, &entry));
+            return Ok(());
+        }
+    };
+    let total: u64 = successors.iter().map(|&(_, count)| count as u64).sum();
+    let mut successors = successors;
+    successors.sort_by(|a, b| b.1.cmp(&a.1));
+    println!("{} -> ({} in total)", entry_str(&dict, &entry), total);
+    for (word, count) in successors {
+        println!(
+            "{:>10} {:>6.2}%  {}",
+            count,
+            100.0 * count as f64 / total as f64,
+            dict.word_str(word)
+        );
+    }
+    Ok(())
+}
+
+pub fn words(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args)?;
+    if args.positional.len() != 1 {
+        return Err(super::USAGE.to_owned());
+    }
+    let pattern = match args.get("match") {
+        Some(pattern) => Some(Regex::new(pattern).map_err(|err| err.to_string())?),
+        None => None,
+    };
+    let dict = load(&args.positional[0])?;
+
+    // how many times each word has been seen
+    let mut counts = HashMap::new();
+    dict.for_each_transition(|_, word, count| {
+        *counts.entry(word).or_insert(0u64) += count as u64;
+    });
+    let mut words: Vec<(Word, u64)> = (0..dict.num_words())
+        .map(|index| Word::Word(index as u32))
+        .filter(|&word| {
+            pattern
+                .as_ref()
+                .map_or(true, |pattern| pattern.is_match(dict.word_str(word)))
+        })
+        .map(|word| (word, counts.get(&word).cloned().unwrap_or(0)))
+        .collect();
+    words.sort_by(|a, b| b.1.cmp(&a.1));
+    for (word, count) in words {
+        println!("{:>10}  {}", count, dict.word_str(word));
+    }
+    Ok(())
+}
+
+fn entry_str(dict: &Dictionary, entry: &[Word]) -> String {
+    let words: Vec<&str> = entry.iter().map(|&word| dict.word_str(word)).collect();
+    words.join(" ")
+}
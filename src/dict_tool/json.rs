@@ -0,0 +1,205 @@
+//! Lossless conversion of dictionaries to and from JSON, for reviewing, editing and keeping them
+//! under version control
+
+use super::{load, save, Args};
+use modules::{Dictionary, Word, MAX_ORDER};
+use serde_json;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Sentinel {
+    Start1,
+    Start2,
+    End,
+}
+
+/// Words are written as themselves, the sentinels as `{"sentinel": "start1"}` etc., so that
+/// they can't be confused with words that happen to look like them
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum JsonWord {
+    Word(String),
+    Sentinel { sentinel: Sentinel },
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonEntry {
+    entry: Vec<JsonWord>,
+    /// the words following the entry, with their counts
+    next: Vec<(JsonWord, u32)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonDictionary {
+    order: usize,
+    /// all the words, in the order of their indices in the binary format
+    words: Vec<String>,
+    entries: Vec<JsonEntry>,
+    /// the hashes of the learned lines, used to avoid repeating them verbatim
+    #[serde(default)]
+    lines: Vec<u64>,
+    /// when the counts were last halved, in seconds since the Unix epoch, 0 if never
+    #[serde(default)]
+    decayed_at: u64,
+}
+
+pub fn export(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args)?;
+    if args.positional.len() != 2 {
+        return Err(super::USAGE.to_owned());
+    }
+    let dict = load(&args.positional[0])?;
+    let output = &args.positional[1];
+
+    let file =
+        File::create(output).map_err(|err| format!("Couldn't create {}: {}", output, err))?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut writer, &to_json(&dict))
+        .map_err(|err| err.to_string())
+        .and_then(|_| writer.write_all(b"\n").map_err(|err| err.to_string()))
+        .map_err(|err| format!("Couldn't write {}: {}", output, err))
+}
+
+fn to_json(dict: &Dictionary) -> JsonDictionary {
+    let to_json = |word: Word| match word {
+        Word::Start1 => JsonWord::Sentinel {
+            sentinel: Sentinel::Start1,
+        },
+        Word::Start2 => JsonWord::Sentinel {
+            sentinel: Sentinel::Start2,
+        },
+        Word::End => JsonWord::Sentinel {
+            sentinel: Sentinel::End,
+        },
+        word => JsonWord::Word(dict.word_str(word).to_owned()),
+    };
+    let mut entries = dict.entries();
+    // sort by the text, so that exports of similar dictionaries give small diffs
+    entries.sort_by_key(|entry| {
+        entry
+            .iter()
+            .map(|&word| dict.word_str(word).to_lowercase())
+            .collect::<Vec<_>>()
+    });
+    JsonDictionary {
+        order: dict.order(),
+        words: (0..dict.num_words())
+            .map(|index| dict.word_str(Word::Word(index as u32)).to_owned())
+            .collect(),
+        entries: entries
+            .into_iter()
+            .map(|entry| JsonEntry {
+                entry: entry.iter().map(|&word| to_json(word)).collect(),
+                next: dict
+                    .successors(&entry)
+                    .unwrap()
+                    .into_iter()
+                    .map(|(word, count)| (to_json(word), count))
+                    .collect(),
+            })
+            .collect(),
+        lines: dict.line_hashes(),
+        decayed_at: dict.decayed_at(),
+    }
+}
+
+pub fn import(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args)?;
+    if args.positional.len() != 2 {
+        return Err(super::USAGE.to_owned());
+    }
+    let input = &args.positional[0];
+    let dict_path = &args.positional[1];
+
+    let file = File::open(input).map_err(|err| format!("Couldn't open {}: {}", input, err))?;
+    let json: JsonDictionary = serde_json::from_reader(BufReader::new(file))
+        .map_err(|err| format!("Couldn't parse {}: {}", input, err))?;
+    let mut dict =
+        from_json(json).map_err(|err| format!("Invalid dictionary in {}: {}", input, err))?;
+    save(&mut dict, dict_path)
+}
+
+fn from_json(json: JsonDictionary) -> Result<Dictionary, String> {
+    if json.order < 1 || json.order > MAX_ORDER {
+        return Err(format!("order must be between 1 and {}", MAX_ORDER));
+    }
+    let mut dict = Dictionary::new(json.order);
+    // add the words first, so that they keep their indices
+    for (index, word) in json.words.iter().enumerate() {
+        if dict.add_word(word) != Word::Word(index as u32) {
+            return Err(format!("duplicate word: {}", word));
+        }
+    }
+    dict.add_line_hashes(json.lines);
+    dict.set_decayed_at(json.decayed_at);
+    for JsonEntry { entry, next } in json.entries {
+        if entry.len() != json.order {
+            return Err(format!("entries must have {} words", json.order));
+        }
+        let entry: Vec<Word> = entry
+            .into_iter()
+            .map(|word| match word {
+                JsonWord::Word(word) => Ok(dict.add_word(word)),
+                JsonWord::Sentinel {
+                    sentinel: Sentinel::Start1,
+                } => Ok(Word::Start1),
+                JsonWord::Sentinel {
+                    sentinel: Sentinel::Start2,
+                } => Ok(Word::Start2),
+                JsonWord::Sentinel {
+                    sentinel: Sentinel::End,
+                } => Err("<end> can't be a part of an entry".to_owned()),
+            })
+            .collect::<Result<_, _>>()?;
+        for (word, count) in next {
+            let word = match word {
+                JsonWord::Word(word) => dict.add_word(word),
+                JsonWord::Sentinel {
+                    sentinel: Sentinel::End,
+                } => Word::End,
+                JsonWord::Sentinel { .. } => {
+                    return Err("only words and <end> can follow an entry".to_owned())
+                }
+            };
+            if count > 0 {
+                dict.add_transitions(entry.clone(), word, count);
+            }
+        }
+    }
+    Ok(dict)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn to_string(dict: &Dictionary) -> String {
+        serde_json::to_string_pretty(&to_json(dict)).unwrap()
+    }
+
+    #[test]
+    fn export_import_export_is_the_same() {
+        let path = env::temp_dir().join(format!("chatbot-test-{}-json.dat", process::id()));
+        let mut dict = Dictionary::new(2);
+        dict.learn_from_line("the cat sat on the mat");
+        dict.learn_from_line("the dog sat");
+        dict.save(&path).unwrap();
+        // a part in the file, and a part learned since
+        dict.learn_from_line("a bird sat on the dog");
+        dict.set_decayed_at(1234567890);
+        fs::remove_file(&path).unwrap();
+
+        let exported = to_string(&dict);
+        assert!(exported.contains("\"start2\""));
+        assert!(exported.contains("\"end\""));
+        assert_eq!(to_json(&dict).lines.len(), 3);
+        let imported = from_json(serde_json::from_str(&exported).unwrap()).unwrap();
+        assert_eq!(imported.decayed_at(), 1234567890);
+        assert_eq!(to_string(&imported), exported);
+    }
+}
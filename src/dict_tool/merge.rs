@@ -0,0 +1,34 @@
+//! Combining dictionaries learned by several instances of the bot
+
+use super::{load, save, Args};
+use modules::Dictionary;
+use std::path::Path;
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args)?;
+    if args.positional.len() < 2 {
+        return Err(super::USAGE.to_owned());
+    }
+    let dict_path = &args.positional[0];
+    let inputs = args.positional[1..]
+        .iter()
+        .map(|path| load(path).map(|dict| (path, dict)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut dict = if Path::new(dict_path).exists() {
+        load(dict_path)?
+    } else {
+        Dictionary::new(inputs[0].1.order())
+    };
+
+    for (path, input) in inputs {
+        dict.merge(&input)
+            .map_err(|err| format!("Couldn't merge {}: {}", path, err))?;
+    }
+    println!(
+        "{} now has {} words and {} entries",
+        dict_path,
+        dict.num_words(),
+        dict.num_entries()
+    );
+    save(&mut dict, dict_path)
+}
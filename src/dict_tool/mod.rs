@@ -0,0 +1,187 @@
+//! `chatbot dict ...` - offline maintenance of RandomChat dictionaries
+
+mod bench;
+mod import;
+mod inspect;
+mod json;
+mod merge;
+mod prune;
+
+use modules::{Dictionary, DictionaryError, Word, DEFAULT_ORDER, MAX_ORDER};
+use std::collections::HashMap;
+use std::io;
+
+const USAGE: &str = "Usage: chatbot dict <command> [options]
+
+Commands:
+    import [--order <n>] [--format <format>] [--tokenizer <tokenizer>] [--skip-nick <nick>]...
+           <dictionary> <input>...
+        Learns from text files, IRC logs or chat exports and saves the result in <dictionary>,
+        creating it if it doesn't exist. Directories are searched recursively. Lines learned
+        before, e.g. from overlapping logs, are skipped.
+        Formats: text (default), irssi, weechat, slack, discord
+        Tokenizers: chat (default), whitespace - should match the one RandomChat uses
+    check <dictionary>
+        Checks the checksum of the dictionary file. The other commands check the files they read
+        too, unlike RandomChat, which only makes sure that they can be used safely.
+    stats [--top <n>] <dictionary>
+        Shows the size of the dictionary and its most frequent transitions.
+    show <dictionary> <word>...
+        Shows the words that can follow the given words, as many as the order of the dictionary.
+        <start1>, <start2> and <end> stand for the beginning and the end of a sentence.
+    words [--match <regex>] <dictionary>
+        Lists the words, most frequent first, optionally only the ones matching <regex>.
+    remove-word [--match <regex>] <dictionary> [<word>...]
+        Removes the words, and everything containing them.
+    remove-entry <dictionary> <word>...
+        Removes the entry for the given words, along with the transitions leading to it.
+    prune --min-count <n> <dictionary>
+        Removes the transitions seen fewer than <n> times.
+    export <dictionary> <output.json>
+        Writes the whole dictionary as JSON.
+    import-json <input.json> <dictionary>
+        Creates a dictionary from JSON written by export, replacing <dictionary>.
+    merge <dictionary> <input>...
+        Adds everything learned by the input dictionaries to <dictionary>, creating it if it
+        doesn't exist. All the dictionaries must have the same order.
+    bench [--lines <n>] [--words <n>] [--samples <n>] [--sentences <n>]
+        Measures learning and generating on a synthetic corpus, and compares choosing the next
+        word by walking all the candidates with the weighted sampling the dictionaries use.";
+
+/// Runs the tool with the arguments following `dict`, returns the exit code
+pub fn run(args: &[String]) -> i32 {
+    let result = match args.first().map(String::as_str) {
+        Some("import") => import::run(&args[1..]),
+        Some("check") => inspect::check(&args[1..]),
+        Some("stats") => inspect::stats(&args[1..]),
+        Some("show") => inspect::show(&args[1..]),
+        Some("words") => inspect::words(&args[1..]),
+        Some("remove-word") => prune::remove_word(&args[1..]),
+        Some("remove-entry") => prune::remove_entry(&args[1..]),
+        Some("prune") => prune::prune(&args[1..]),
+        Some("export") => json::export(&args[1..]),
+        Some("import-json") => json::import(&args[1..]),
+        Some("merge") => merge::run(&args[1..]),
+        Some("bench") => bench::run(&args[1..]),
+        _ => Err(USAGE.to_owned()),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
+
+/// Command line arguments split into `--name value` options and positional arguments
+struct Args {
+    options: HashMap<String, Vec<String>>,
+    positional: Vec<String>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Args, String> {
+        let mut options = HashMap::new();
+        let mut positional = Vec::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if arg.starts_with("--") {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| format!("Missing value for {}", arg))?;
+                options
+                    .entry(arg[2..].to_owned())
+                    .or_insert_with(Vec::new)
+                    .push(value.clone());
+            } else {
+                positional.push(arg.clone());
+            }
+        }
+        Ok(Args {
+            options,
+            positional,
+        })
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.options
+            .get(name)
+            .and_then(|values| values.last())
+            .map(String::as_str)
+    }
+
+    fn get_all(&self, name: &str) -> &[String] {
+        self.options
+            .get(name)
+            .map(|values| &values[..])
+            .unwrap_or(&[])
+    }
+
+    fn get_parsed<T: ::std::str::FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        match self.get(name) {
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("Invalid value for --{}: {}", name, value)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Loads the dictionary and checks the whole file, as the tool can take its time
+fn load_verified(path: &str) -> Result<Dictionary, DictionaryError> {
+    let dict = Dictionary::load(path)?;
+    dict.verify()?;
+    Ok(dict)
+}
+
+fn load(path: &str) -> Result<Dictionary, String> {
+    load_verified(path).map_err(|err| format!("Couldn't load {}: {}", path, err))
+}
+
+/// Loads the dictionary at `path`, or creates an empty one if the file doesn't exist
+fn load_or_create(path: &str, order: Option<usize>) -> Result<Dictionary, String> {
+    match load_verified(path) {
+        Ok(dict) => {
+            if order.map_or(false, |order| order != dict.order()) {
+                return Err(format!(
+                    "{} has order {}, which can't be changed",
+                    path,
+                    dict.order()
+                ));
+            }
+            Ok(dict)
+        }
+        Err(DictionaryError::Io(ref err)) if err.kind() == io::ErrorKind::NotFound => {
+            let order = order.unwrap_or(DEFAULT_ORDER);
+            if order < 1 || order > MAX_ORDER {
+                return Err(format!("The order must be between 1 and {}", MAX_ORDER));
+            }
+            Ok(Dictionary::new(order))
+        }
+        Err(err) => Err(format!("Couldn't load {}: {}", path, err)),
+    }
+}
+
+/// Looks up the words of an entry given on the command line
+fn parse_entry(dict: &Dictionary, words: &[String]) -> Result<Vec<Word>, String> {
+    if words.len() != dict.order() {
+        return Err(format!(
+            "Expected {} words, as many as the order of the dictionary",
+            dict.order()
+        ));
+    }
+    words
+        .iter()
+        .map(|word| {
+            dict.find_word(word)
+                .ok_or_else(|| format!("Unknown word: {}", word))
+        })
+        .collect()
+}
+
+fn save(dict: &mut Dictionary, path: &str) -> Result<(), String> {
+    dict.save(path)
+        .map_err(|err| format!("Couldn't save {}: {}", path, err))
+}
@@ -0,0 +1,83 @@
+//! Commands removing unwanted content from a dictionary
+
+use super::{load, parse_entry, save, Args};
+use modules::Word;
+use regex::Regex;
+
+pub fn remove_word(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args)?;
+    if args.positional.is_empty() || (args.positional.len() == 1 && args.get("match").is_none()) {
+        return Err(super::USAGE.to_owned());
+    }
+    let dict_path = &args.positional[0];
+    let mut dict = load(dict_path)?;
+
+    let mut words = Vec::new();
+    for word in &args.positional[1..] {
+        match dict.find_word(word) {
+            Some(word @ Word::Word(_)) => words.push(word),
+            _ => println!("Unknown word: {}", word),
+        }
+    }
+    if let Some(pattern) = args.get("match") {
+        let pattern = Regex::new(pattern).map_err(|err| err.to_string())?;
+        words.extend(
+            (0..dict.num_words())
+                .map(|index| Word::Word(index as u32))
+                .filter(|&word| pattern.is_match(dict.word_str(word))),
+        );
+    }
+    let entries = dict.num_entries();
+    for &word in &words {
+        dict.remove_word(word);
+    }
+    let removed_entries = entries - dict.num_entries();
+    let removed_words = dict.compact();
+    println!(
+        "Removed {} words and {} entries",
+        removed_words, removed_entries
+    );
+    save(&mut dict, dict_path)
+}
+
+pub fn remove_entry(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args)?;
+    if args.positional.len() < 2 {
+        return Err(super::USAGE.to_owned());
+    }
+    let dict_path = &args.positional[0];
+    let mut dict = load(dict_path)?;
+    let entry = parse_entry(&dict, &args.positional[1..])?;
+
+    if !dict.remove_entry(&entry) {
+        return Err("No such entry".to_owned());
+    }
+    let removed_words = dict.compact();
+    println!(
+        "Removed the entry and {} words no longer used",
+        removed_words
+    );
+    save(&mut dict, dict_path)
+}
+
+pub fn prune(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args)?;
+    if args.positional.len() != 1 {
+        return Err(super::USAGE.to_owned());
+    }
+    let min_count = args
+        .get_parsed("min-count")?
+        .ok_or_else(|| "Missing --min-count".to_owned())?;
+    let dict_path = &args.positional[0];
+    let mut dict = load(dict_path)?;
+
+    let entries = dict.num_entries();
+    let removed_transitions = dict.prune(min_count);
+    let removed_entries = entries - dict.num_entries();
+    let removed_words = dict.compact();
+    println!(
+        "Removed {} transitions, {} entries and {} words",
+        removed_transitions, removed_entries, removed_words
+    );
+    save(&mut dict, dict_path)
+}
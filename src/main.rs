@@ -1,34 +1,47 @@
 extern crate chrono;
 #[macro_use]
 extern crate lazy_static;
+extern crate memmap;
 extern crate rand;
 extern crate regex;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
 extern crate toml;
 #[macro_use]
 extern crate universal_chat;
 
 mod config;
+mod dict_tool;
 mod modules;
 
 use config::CONFIG;
-use modules::{Eightball, MsgPipe, Patterns, RandomChat};
+use modules::{check_commands, Eightball, Help, MsgPipe, Patterns, RandomChat};
 use std::collections::HashMap;
+use std::env;
+use std::process;
 use universal_chat::{Core, ModuleBuilder};
 
 fn main() {
+    let args: Vec<String> = env::args().collect();
+    // `chatbot dict ...` runs the dictionary tool instead of the bot
+    if args.get(1).map(String::as_str) == Some("dict") {
+        process::exit(dict_tool::run(&args[2..]));
+    }
     let mut builders = HashMap::<String, ModuleBuilder>::new();
     builders.insert("MsgPipe".to_owned(), MsgPipe::create);
     builders.insert("RandomChat".to_owned(), RandomChat::create);
     builders.insert("Patterns".to_owned(), Patterns::create);
     builders.insert("Eightball".to_owned(), Eightball::create);
+    builders.insert("Help".to_owned(), Help::create);
     // Create a core object
     let mut core = {
         let config = CONFIG.lock().ok().expect("Couldn't lock CONFIG");
         Core::new(&builders, &*config)
     };
+    // The modules have declared their commands while being created
+    check_commands();
     // Connect all event sources
     core.connect_all();
     // Run the event processing loop
@@ -0,0 +1,590 @@
+//! Commands - messages starting with the command character - and the modules handling them.
+//! Modules declare their commands with `register` when they are created, and find out which
+//! messages are theirs with `dispatch`.
+
+use config::CONFIG;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::str::FromStr;
+use std::sync::Mutex;
+use universal_chat::{Channel, Message, MessageContent, SourceId};
+
+#[derive(Clone, Debug)]
+pub struct Command {
+    pub sender: String,
+    pub channel: Channel,
+    pub params: Vec<String>,
+    /// the text after the command char, as written
+    text: String,
+    /// where each parameter starts in `text`
+    starts: Vec<usize>,
+}
+
+pub fn command_char() -> String {
+    CONFIG
+        .lock()
+        .ok()
+        .expect("Couldn't lock CONFIG")
+        .custom
+        .command_char
+        .clone()
+}
+
+fn unknown_command_reply() -> bool {
+    CONFIG
+        .lock()
+        .ok()
+        .expect("Couldn't lock CONFIG")
+        .custom
+        .unknown_command_reply
+        .unwrap_or(false)
+}
+
+/// Splits the text of a command into parameters like a shell: on any whitespace, except inside
+/// double quotes. A backslash escapes a double quote, a backslash or whitespace; other
+/// backslashes and single quotes, which are mostly apostrophes in chat, are kept as they are.
+/// A quote left open lasts until the end of the text. Returns the parameters with the byte
+/// offsets where they start.
+fn split_params(text: &str) -> Vec<(usize, String)> {
+    let mut params = Vec::new();
+    let mut param = String::new();
+    // so that "" gives an empty parameter
+    let mut start = None;
+    let mut quoted = false;
+    let mut chars = text.char_indices().peekable();
+    while let Some((pos, c)) = chars.next() {
+        match c {
+            '\\' => {
+                match chars.peek() {
+                    Some(&(_, next)) if next == '"' || next == '\\' || next.is_whitespace() => {
+                        param.push(next);
+                        chars.next();
+                    }
+                    _ => param.push(c),
+                }
+                start = start.or(Some(pos));
+            }
+            '"' => {
+                quoted = !quoted;
+                start = start.or(Some(pos));
+            }
+            c if c.is_whitespace() && !quoted => {
+                if let Some(start) = start.take() {
+                    params.push((start, mem::replace(&mut param, String::new())));
+                }
+            }
+            c => {
+                param.push(c);
+                start = start.or(Some(pos));
+            }
+        }
+    }
+    if let Some(start) = start {
+        params.push((start, param));
+    }
+    params
+}
+
+impl Command {
+    /// The command char has to be followed by the command, so e.g. "!" and "! wow" aren't
+    /// commands
+    pub fn from_msg<'a>(msg: &'a Message) -> Option<Command> {
+        if let MessageContent::Text(txt) = msg.content.clone() {
+            let cmd_char = command_char();
+            if !txt.starts_with(&cmd_char) {
+                return None;
+            }
+            let text = &txt[cmd_char.len()..];
+            if text.is_empty() || text.starts_with(char::is_whitespace) {
+                return None;
+            }
+            Some(Command::new(msg.author.clone(), msg.channel.clone(), text))
+        } else {
+            None
+        }
+    }
+
+    /// `text` is what follows the command char
+    fn new(sender: String, channel: Channel, text: &str) -> Command {
+        let (starts, params) = split_params(text).into_iter().unzip();
+        Command {
+            sender,
+            channel,
+            params,
+            text: text.to_owned(),
+            starts,
+        }
+    }
+
+    /// The parameter at `index`, the command itself being 0
+    pub fn param(&self, index: usize) -> Option<&str> {
+        self.params.get(index).map(String::as_str)
+    }
+
+    /// The parameter at `index` parsed as `T`: `None` if it's missing, an error message if it
+    /// can't be parsed
+    pub fn parsed<T: FromStr>(&self, index: usize) -> Option<Result<T, String>> {
+        self.param(index).map(|param| {
+            param
+                .parse()
+                .map_err(|_| format!("Invalid value: {}", param))
+        })
+    }
+
+    /// The parameters from `index` to the end joined with spaces, the quotes and the escapes
+    /// removed, or `None` if there are no parameters there
+    pub fn words(&self, index: usize) -> Option<String> {
+        self.params
+            .get(index..)
+            .filter(|words| !words.is_empty())
+            .map(|words| words.join(" "))
+    }
+
+    /// The text from the parameter at `index` to the end, as written - with the quotes and
+    /// the escapes - or `None` if there are no parameters there
+    pub fn rest(&self, index: usize) -> Option<String> {
+        self.starts
+            .get(index)
+            .map(|&start| self.text[start..].trim_end().to_owned())
+    }
+}
+
+/// An argument of a command
+#[derive(Clone, Debug)]
+pub struct ArgSpec {
+    pub name: &'static str,
+    pub optional: bool,
+    /// takes the rest of the line
+    pub rest: bool,
+}
+
+impl ArgSpec {
+    /// A single required word
+    pub fn word(name: &'static str) -> ArgSpec {
+        ArgSpec {
+            name,
+            optional: false,
+            rest: false,
+        }
+    }
+
+    /// The rest of the line, at least one word unless optional
+    pub fn rest(name: &'static str) -> ArgSpec {
+        ArgSpec {
+            name,
+            optional: false,
+            rest: true,
+        }
+    }
+
+    pub fn optional(mut self) -> ArgSpec {
+        self.optional = true;
+        self
+    }
+}
+
+impl fmt::Display for ArgSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let dots = if self.rest { "..." } else { "" };
+        if self.optional {
+            write!(f, "[{}{}]", self.name, dots)
+        } else {
+            write!(f, "<{}{}>", self.name, dots)
+        }
+    }
+}
+
+/// A command or a subcommand, as declared by the module handling it
+#[derive(Clone, Debug)]
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub aliases: Vec<&'static str>,
+    pub args: Vec<ArgSpec>,
+    pub help: &'static str,
+    /// chosen by the first argument, in place of `args`
+    pub subcommands: Vec<CommandSpec>,
+}
+
+impl CommandSpec {
+    pub fn new(name: &'static str, help: &'static str) -> CommandSpec {
+        CommandSpec {
+            name,
+            aliases: Vec::new(),
+            args: Vec::new(),
+            help,
+            subcommands: Vec::new(),
+        }
+    }
+
+    pub fn alias(mut self, alias: &'static str) -> CommandSpec {
+        self.aliases.push(alias);
+        self
+    }
+
+    pub fn arg(mut self, arg: ArgSpec) -> CommandSpec {
+        self.args.push(arg);
+        self
+    }
+
+    pub fn subcommand(mut self, subcommand: CommandSpec) -> CommandSpec {
+        self.subcommands.push(subcommand);
+        self
+    }
+
+    /// Whether `word` is the name or one of the aliases
+    pub fn matches(&self, word: &str) -> bool {
+        self.name == word || self.aliases.contains(&word)
+    }
+
+    /// What follows the name in the usage, e.g. "<word> [text...]" or "<on|off>"
+    pub fn usage_args(&self) -> String {
+        if !self.subcommands.is_empty() {
+            let names: Vec<&str> = self.subcommands.iter().map(|sub| sub.name).collect();
+            return format!("<{}>", names.join("|"));
+        }
+        let args: Vec<String> = self.args.iter().map(ArgSpec::to_string).collect();
+        args.join(" ")
+    }
+
+    /// "<path> <args> - <help>", `path` being the names of the command and of its parents
+    pub fn usage(&self, path: &str) -> String {
+        let usage = format!("{} {}", path, self.usage_args());
+        format!("{} - {}", usage.trim_end(), self.help)
+    }
+
+    /// Checks the number of arguments and replaces the aliases with the names. `params[0]` is
+    /// this command, `path` the commands before it. Returns the usage and the help of the command
+    /// on errors.
+    fn check(&self, path: &str, params: &mut [String]) -> Result<(), String> {
+        params[0] = self.name.to_owned();
+        let path = if path.is_empty() {
+            self.name.to_owned()
+        } else {
+            format!("{} {}", path, self.name)
+        };
+        if !self.subcommands.is_empty() {
+            let found = params
+                .get(1)
+                .and_then(|param| self.subcommands.iter().find(|sub| sub.matches(param)));
+            return match found {
+                Some(sub) => sub.check(&path, &mut params[1..]),
+                None => Err(self.usage(&path)),
+            };
+        }
+        let given = params.len() - 1;
+        let required = self.args.iter().filter(|arg| !arg.optional).count();
+        let unlimited = self.args.last().map_or(false, |arg| arg.rest);
+        if given < required || (!unlimited && given > self.args.len()) {
+            return Err(self.usage(&path));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    /// module ids and their commands, in the order of registration
+    modules: Vec<(String, Vec<CommandSpec>)>,
+    /// the sources the modules are subscribed to, filled in by `check_commands`
+    sources: HashMap<String, Vec<String>>,
+}
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry::default());
+}
+
+impl Registry {
+    fn is_subscribed(&self, module_id: &str, source: &str) -> bool {
+        self.sources
+            .get(module_id)
+            .map_or(false, |sources| sources.iter().any(|name| name == source))
+    }
+
+    /// The module handling the command on the source
+    fn owner(&self, source: &str, name: &str) -> Option<&str> {
+        self.modules
+            .iter()
+            .filter(|&&(ref id, _)| self.is_subscribed(id, source))
+            .find(|&&(_, ref specs)| specs.iter().any(|spec| spec.matches(name)))
+            .map(|&(ref id, _)| &id[..])
+    }
+
+    /// The commands declared by more than one module subscribed to the same source, as
+    /// "<name> on <source> (<module ids>)"
+    fn conflicts(&self) -> Vec<String> {
+        let mut sources: Vec<&String> = self.sources.values().flat_map(|v| v.iter()).collect();
+        sources.sort();
+        sources.dedup();
+        let mut conflicts = Vec::new();
+        for source in sources {
+            let mut owners: HashMap<&str, Vec<&str>> = HashMap::new();
+            for &(ref id, ref specs) in &self.modules {
+                if !self.is_subscribed(id, source) {
+                    continue;
+                }
+                for spec in specs {
+                    for name in Some(&spec.name).into_iter().chain(&spec.aliases) {
+                        owners.entry(name).or_insert_with(Vec::new).push(id);
+                    }
+                }
+            }
+            let mut names: Vec<_> = owners
+                .into_iter()
+                .filter(|&(_, ref ids)| ids.len() > 1)
+                .collect();
+            names.sort();
+            for (name, ids) in names {
+                conflicts.push(format!("{} on {} ({})", name, source, ids.join(", ")));
+            }
+        }
+        conflicts
+    }
+
+    /// The module answering or logging commands nobody handles on the source: the first one by
+    /// id, so that it's done once
+    fn fallback(&self, source: &str) -> Option<&str> {
+        self.modules
+            .iter()
+            .map(|&(ref id, _)| &id[..])
+            .filter(|id| self.is_subscribed(id, source))
+            .min()
+    }
+}
+
+/// The commands of the modules subscribed to the source, sorted by name
+pub fn commands_on(source: &SourceId) -> Vec<CommandSpec> {
+    let registry = REGISTRY.lock().ok().expect("Couldn't lock REGISTRY");
+    let mut commands: Vec<CommandSpec> = registry
+        .modules
+        .iter()
+        .filter(|&&(ref id, _)| registry.is_subscribed(id, &source.0))
+        .flat_map(|&(_, ref specs)| specs.iter().cloned())
+        .collect();
+    commands.sort_by_key(|spec| spec.name);
+    commands
+}
+
+/// Declares the commands handled by the module. Called when the module is created.
+pub fn register(module_id: &str, specs: Vec<CommandSpec>) {
+    let mut registry = REGISTRY.lock().ok().expect("Couldn't lock REGISTRY");
+    registry.modules.push((module_id.to_owned(), specs));
+}
+
+/// Looks up the sources of the modules in the config and checks that no two modules subscribed
+/// to the same source declare the same command. Panics listing the conflicts otherwise.
+pub fn check_commands() {
+    // CONFIG is never locked with REGISTRY held, as `register` is called with CONFIG locked
+    let sources: HashMap<String, Vec<String>> = CONFIG
+        .lock()
+        .ok()
+        .expect("Couldn't lock CONFIG")
+        .modules
+        .iter()
+        .map(|(id, module)| (id.clone(), module.subscriptions.keys().cloned().collect()))
+        .collect();
+    let mut registry = REGISTRY.lock().ok().expect("Couldn't lock REGISTRY");
+    registry.sources = sources;
+    let conflicts = registry.conflicts();
+    if !conflicts.is_empty() {
+        panic!(
+            "Commands declared by more than one module: {}",
+            conflicts.join("; ")
+        );
+    }
+}
+
+/// What a module should do with a message
+pub enum Dispatch {
+    /// not a command
+    NotCommand,
+    /// a command of another module
+    Other,
+    /// a command of this module, with valid arguments
+    Run(Command),
+    /// a command the module should answer with this text, e.g. with the usage when the
+    /// arguments are invalid
+    Reply(String),
+}
+
+/// Decides whether the message is a command of the module `module_id`. Names made of
+/// punctuation, like in "!!!", aren't treated as commands. Commands no module handles are only
+/// logged, unless `unknown_command_reply` is set in the config.
+pub fn dispatch(module_id: &str, source: &SourceId, msg: &Message) -> Dispatch {
+    let mut cmd = match Command::from_msg(msg) {
+        Some(cmd) => cmd,
+        None => return Dispatch::NotCommand,
+    };
+    let named = cmd.params[0]
+        .chars()
+        .next()
+        .map_or(false, char::is_alphanumeric);
+    // read before locking REGISTRY, see check_commands
+    let (cmd_char, reply_unknown) = (command_char(), unknown_command_reply());
+    let registry = REGISTRY.lock().ok().expect("Couldn't lock REGISTRY");
+    match registry.owner(&source.0, &cmd.params[0]) {
+        Some(owner) if owner == module_id => {
+            let spec = registry
+                .modules
+                .iter()
+                .filter(|&&(ref id, _)| id == module_id)
+                .flat_map(|&(_, ref specs)| specs)
+                .find(|spec| spec.matches(&cmd.params[0]))
+                .unwrap();
+            match spec.check("", &mut cmd.params) {
+                Ok(()) => Dispatch::Run(cmd),
+                Err(usage) => Dispatch::Reply(format!("Usage: {}{}", cmd_char, usage)),
+            }
+        }
+        Some(_) => Dispatch::Other,
+        None if named && registry.fallback(&source.0) == Some(module_id) => {
+            let mut text = format!("Unknown command: {}{}", cmd_char, cmd.params[0]);
+            if !reply_unknown {
+                eprintln!("{} on {} from {}", text, source.0, cmd.sender);
+                return Dispatch::Other;
+            }
+            if registry.owner(&source.0, "help").is_some() {
+                text.push_str(&format!(", see {}help", cmd_char));
+            }
+            Dispatch::Reply(text)
+        }
+        None => Dispatch::Other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(text: &str) -> Vec<String> {
+        split_params(text)
+            .into_iter()
+            .map(|(_, param)| param)
+            .collect()
+    }
+
+    #[test]
+    fn splits_like_a_shell() {
+        assert_eq!(params("a  b\t c\nd"), vec!["a", "b", "c", "d"]);
+        assert_eq!(
+            params(r#"eightball "will it rain" ok"#),
+            vec!["eightball", "will it rain", "ok"]
+        );
+        assert_eq!(params(r#"x "" y"#), vec!["x", "", "y"]);
+        assert_eq!(params(r#"x say"hi there""#), vec!["x", "sayhi there"]);
+        assert_eq!(params(r#"x \"a\" b\ c"#), vec!["x", "\"a\"", "b c"]);
+        assert_eq!(
+            params(r#"x "open to the end"#),
+            vec!["x", "open to the end"]
+        );
+        // apostrophes and other backslashes are kept
+        assert_eq!(params(r"gadaj it's \o/"), vec!["gadaj", "it's", r"\o/"]);
+    }
+
+    #[test]
+    fn rest_is_the_text_as_written() {
+        let command = Command::new(
+            "nick".to_owned(),
+            Channel::Channel("#test".to_owned()),
+            r#"random forget  my password is "hunter2" ok "#,
+        );
+        assert_eq!(command.param(2), Some("my"));
+        assert_eq!(
+            command.rest(2),
+            Some(r#"my password is "hunter2" ok"#.to_owned())
+        );
+        assert_eq!(command.rest(7), None);
+        assert_eq!(
+            command.words(2),
+            Some("my password is hunter2 ok".to_owned())
+        );
+        assert_eq!(command.words(7), None);
+        let command = Command::new(
+            "nick".to_owned(),
+            Channel::Channel("#test".to_owned()),
+            r#"random forget "a b" \"c\""#,
+        );
+        assert_eq!(command.words(2), Some(r#"a b "c""#.to_owned()));
+    }
+
+    fn random() -> CommandSpec {
+        CommandSpec::new("random", "Random chat")
+            .alias("r")
+            .subcommand(
+                CommandSpec::new("forget", "Forgets a line")
+                    .alias("f")
+                    .arg(ArgSpec::rest("line")),
+            )
+            .subcommand(
+                CommandSpec::new("temperature", "Sets the temperature")
+                    .arg(ArgSpec::word("value").optional()),
+            )
+    }
+
+    fn check(spec: &CommandSpec, text: &str) -> Result<Vec<String>, String> {
+        let mut params = params(text);
+        spec.check("", &mut params).map(|_| params)
+    }
+
+    #[test]
+    fn aliases_are_replaced_and_arguments_counted() {
+        let random = random();
+        assert_eq!(
+            check(&random, "r f a b c"),
+            Ok(vec!["random", "forget", "a", "b", "c"]
+                .into_iter()
+                .map(str::to_owned)
+                .collect())
+        );
+        assert!(check(&random, "random temperature").is_ok());
+        assert!(check(&random, "random temperature 1.5").is_ok());
+        assert_eq!(
+            check(&random, "random temperature 1 2"),
+            Err("random temperature [value] - Sets the temperature".to_owned())
+        );
+        assert_eq!(
+            check(&random, "r forget"),
+            Err("random forget <line...> - Forgets a line".to_owned())
+        );
+        assert_eq!(
+            check(&random, "r"),
+            Err("random <forget|temperature> - Random chat".to_owned())
+        );
+        assert_eq!(
+            check(&random, "r recall"),
+            Err("random <forget|temperature> - Random chat".to_owned())
+        );
+    }
+
+    fn registry(modules: &[(&str, &[&str], &[CommandSpec])]) -> Registry {
+        let mut registry = Registry::default();
+        for &(id, sources, specs) in modules {
+            registry.modules.push((id.to_owned(), specs.to_vec()));
+            let sources = sources.iter().map(|&source| source.to_owned()).collect();
+            registry.sources.insert(id.to_owned(), sources);
+        }
+        registry
+    }
+
+    #[test]
+    fn conflicts_are_found_per_source() {
+        let help = CommandSpec::new("help", "Help");
+        let registry = registry(&[
+            ("chat", &["irc", "discord"], &[random()]),
+            ("help", &["irc", "discord"], &[help.clone()]),
+            ("help2", &["discord"], &[help.clone().alias("r")]),
+            ("other", &["slack"], &[help]),
+        ]);
+        assert_eq!(
+            registry.conflicts(),
+            vec![
+                "help on discord (help, help2)",
+                "r on discord (chat, help2)"
+            ]
+        );
+        assert_eq!(registry.owner("irc", "r"), Some("chat"));
+        assert_eq!(registry.owner("slack", "random"), None);
+        assert_eq!(registry.fallback("discord"), Some("chat"));
+        assert_eq!(registry.fallback("telegram"), None);
+    }
+}
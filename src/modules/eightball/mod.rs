@@ -1,5 +1,5 @@
-use modules::Command;
-use rand::{thread_rng, Rng};
+use modules::{dispatch, module_rng, register, ArgSpec, CommandSpec, Dispatch, ModuleRng};
+use rand::Rng;
 use toml::Value;
 use universal_chat::{
     CoreAPI, Event, Message, MessageContent, Module, ResumeEventHandling, SourceEvent,
@@ -8,13 +8,14 @@ use universal_chat::{
 #[derive(Clone, Debug, Deserialize)]
 struct EightballConfig {
     responses: Vec<String>,
+    seed: Option<u64>,
 }
 
 pub struct Eightball {
-    #[allow(unused)]
     module_id: String,
     enabled: bool,
     config: EightballConfig,
+    rng: ModuleRng,
 }
 
 impl Eightball {
@@ -24,12 +25,28 @@ impl Eightball {
             .try_into()
             .ok()
             .expect("Failed parsing a Value into EightballConfig");
+        register(
+            &id,
+            vec![
+                CommandSpec::new("eightball", "Answers a yes or no question")
+                    .alias("8ball")
+                    .arg(ArgSpec::rest("question")),
+            ],
+        );
         Box::new(Eightball {
             module_id: id,
             enabled: true,
+            rng: module_rng(config.seed),
             config,
         })
     }
+
+    /// A random response, addressed to `author`
+    fn answer(&mut self, author: &str) -> Option<String> {
+        self.rng
+            .choose(&self.config.responses)
+            .map(|response| response.replace("%s", author))
+    }
 }
 
 impl Module for Eightball {
@@ -39,27 +56,51 @@ impl Module for Eightball {
             return ResumeEventHandling::Resume;
         }
         match event {
-            Event::ReceivedMessage(msg) => if let Some(cmd) = Command::from_msg(&msg) {
-                if cmd.params[0] == "eightball" && cmd.params.len() > 1 {
+            Event::ReceivedMessage(msg) => {
+                let response = match dispatch(&self.module_id, &source, &msg) {
                     //TODO: validate question?
-                    if let Some(response) = thread_rng().choose(&self.config.responses) {
-                        let response = response.replace("%s", &msg.author);
-                        core.send(
-                            &source,
-                            Message {
-                                author: "".to_owned(),
-                                channel: msg.channel.clone(),
-                                content: MessageContent::Text(response),
-                            },
-                        );
-                    }
+                    Dispatch::Run(_) => self.answer(&msg.author),
+                    Dispatch::Reply(text) => Some(text),
+                    // ignore non-commands and the commands of other modules
+                    Dispatch::NotCommand | Dispatch::Other => None,
+                };
+                if let Some(response) = response {
+                    core.send(
+                        &source,
+                        Message {
+                            author: "".to_owned(),
+                            channel: msg.channel.clone(),
+                            content: MessageContent::Text(response),
+                        },
+                    );
                 }
                 ResumeEventHandling::Resume
-            } else {
-                // ignore non-commands
-                ResumeEventHandling::Resume
-            },
+            }
             _ => ResumeEventHandling::Resume,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_answers_stay_the_same() {
+        let mut eightball = Eightball {
+            module_id: "eightball".to_owned(),
+            enabled: true,
+            config: EightballConfig {
+                responses: vec!["Yes, %s.".to_owned(), "No.".to_owned(), "Maybe.".to_owned()],
+                seed: Some(42),
+            },
+            rng: module_rng(Some(42)),
+        };
+        let answers: Vec<String> = (0..6).map(|_| eightball.answer("alice").unwrap()).collect();
+        // changes only if the generator or the way it's used to choose does
+        assert_eq!(
+            answers,
+            vec!["No.", "Maybe.", "Maybe.", "Yes, alice.", "Maybe.", "No."]
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use modules::{command_char, commands_on, dispatch, register, ArgSpec, CommandSpec, Dispatch};
+use toml::Value;
+use universal_chat::{
+    CoreAPI, Event, Message, MessageContent, Module, ResumeEventHandling, SourceEvent,
+};
+
+/// Lists the commands available on a source, as declared by the modules subscribed to it
+pub struct Help {
+    module_id: String,
+}
+
+impl Help {
+    pub fn create(id: String, _config: Option<Value>) -> Box<Module> {
+        register(
+            &id,
+            vec![
+                CommandSpec::new("help", "Lists the commands, or describes one")
+                    .arg(ArgSpec::rest("command").optional()),
+            ],
+        );
+        Box::new(Help { module_id: id })
+    }
+}
+
+/// " (also <aliases>)", or nothing if the command has no aliases
+fn aliases(prefix: &str, spec: &CommandSpec) -> String {
+    if spec.aliases.is_empty() {
+        return String::new();
+    }
+    let aliases: Vec<String> = spec
+        .aliases
+        .iter()
+        .map(|alias| format!("{}{}", prefix, alias))
+        .collect();
+    format!(" (also {})", aliases.join(", "))
+}
+
+/// `help`: all the commands, with their arguments and aliases
+fn list(cmd_char: &str, commands: &[CommandSpec]) -> String {
+    let commands: Vec<String> = commands
+        .iter()
+        .map(|spec| {
+            let usage = format!("{}{} {}", cmd_char, spec.name, spec.usage_args());
+            format!("{}{}", usage.trim_end(), aliases(cmd_char, spec))
+        })
+        .collect();
+    format!(
+        "Commands: {}. Use {}help <command> for details.",
+        commands.join(", "),
+        cmd_char
+    )
+}
+
+/// `help <command> [subcommand...]`: the usage of the command. The subcommands are only named,
+/// so that the answer stays short.
+fn describe(cmd_char: &str, commands: &[CommandSpec], path: &[String]) -> String {
+    let name = path[0].trim_start_matches(cmd_char);
+    let mut spec = match commands.iter().find(|spec| spec.matches(name)) {
+        Some(spec) => spec,
+        None => return format!("No such command: {}{}", cmd_char, name),
+    };
+    let mut names = vec![spec.name];
+    for word in &path[1..] {
+        spec = match spec.subcommands.iter().find(|sub| sub.matches(word)) {
+            Some(sub) => sub,
+            None => return format!("{}{} has no subcommand {}", cmd_char, names.join(" "), word),
+        };
+        names.push(spec.name);
+    }
+    let mut text = format!("{}{}", cmd_char, spec.usage(&names.join(" ")));
+    // only the commands themselves are written with the command char
+    text.push_str(&aliases(if names.len() == 1 { cmd_char } else { "" }, spec));
+    if !spec.subcommands.is_empty() {
+        text.push_str(&format!(
+            ". Use {}help {} <subcommand> for details.",
+            cmd_char,
+            names.join(" ")
+        ));
+    }
+    text
+}
+
+impl Module for Help {
+    fn handle_event(&mut self, core: &mut CoreAPI, event: SourceEvent) -> ResumeEventHandling {
+        let SourceEvent { source, event } = event;
+        match event {
+            Event::ReceivedMessage(msg) => {
+                let text = match dispatch(&self.module_id, &source, &msg) {
+                    Dispatch::Run(cmd) => {
+                        let (cmd_char, commands) = (command_char(), commands_on(&source));
+                        match cmd.param(1) {
+                            Some(_) => describe(&cmd_char, &commands, &cmd.params[1..]),
+                            None => list(&cmd_char, &commands),
+                        }
+                    }
+                    Dispatch::Reply(text) => text,
+                    Dispatch::NotCommand | Dispatch::Other => return ResumeEventHandling::Resume,
+                };
+                core.send(
+                    &source,
+                    Message {
+                        author: "".to_owned(),
+                        channel: msg.channel,
+                        content: MessageContent::Text(text),
+                    },
+                );
+                ResumeEventHandling::Stop
+            }
+            _ => ResumeEventHandling::Resume,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands() -> Vec<CommandSpec> {
+        vec![
+            CommandSpec::new("gadaj", "Says something random")
+                .alias("g")
+                .arg(ArgSpec::rest("words").optional()),
+            CommandSpec::new("help", "Lists the commands"),
+            CommandSpec::new("random", "Random chat settings").subcommand(
+                CommandSpec::new("forget", "Forgets a line")
+                    .alias("f")
+                    .arg(ArgSpec::rest("text")),
+            ),
+        ]
+    }
+
+    fn path(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn listing_shows_usage_and_aliases() {
+        assert_eq!(
+            list("!", &commands()),
+            "Commands: !gadaj [words...] (also !g), !help, !random <forget>. \
+             Use !help <command> for details."
+        );
+    }
+
+    #[test]
+    fn commands_are_described() {
+        let commands = commands();
+        assert_eq!(
+            describe("!", &commands, &path("g")),
+            "!gadaj [words...] - Says something random (also !g)"
+        );
+        assert_eq!(
+            describe("!", &commands, &path("!help")),
+            "!help - Lists the commands"
+        );
+        assert_eq!(
+            describe("!", &commands, &path("random")),
+            "!random <forget> - Random chat settings. \
+             Use !help random <subcommand> for details."
+        );
+        assert_eq!(
+            describe("!", &commands, &path("random f")),
+            "!random forget <text...> - Forgets a line (also f)"
+        );
+    }
+
+    #[test]
+    fn unknown_topics_are_reported() {
+        let commands = commands();
+        assert_eq!(
+            describe("!", &commands, &path("!nope")),
+            "No such command: !nope"
+        );
+        assert_eq!(
+            describe("!", &commands, &path("random nope")),
+            "!random has no subcommand nope"
+        );
+        assert_eq!(
+            describe("!", &commands, &path("help forget")),
+            "!help has no subcommand forget"
+        );
+    }
+}
@@ -1,44 +1,67 @@
+mod commands;
 mod eightball;
+mod help;
 mod msg_pipe;
 mod patterns;
 mod randomchat;
 
+pub use self::commands::{
+    check_commands, command_char, commands_on, dispatch, register, ArgSpec, Command, CommandSpec,
+    Dispatch,
+};
 pub use self::eightball::Eightball;
+pub use self::help::Help;
 pub use self::msg_pipe::MsgPipe;
 pub use self::patterns::Patterns;
-pub use self::randomchat::RandomChat;
-use config::CONFIG;
-use universal_chat::{Channel, Message, MessageContent};
+pub use self::randomchat::{
+    tokenizer, Dictionary, DictionaryError, Quality, RandomChat, Sampling, Successors, Word,
+    DEFAULT_ORDER, MAX_ORDER,
+};
+use rand::prng::ChaChaRng;
+use rand::{FromEntropy, SeedableRng};
+use universal_chat::{Channel, CoreAPI, Message, MessageContent, SourceId};
 
-#[derive(Clone, Debug)]
-pub struct Command {
-    pub sender: String,
-    pub channel: Channel,
-    pub params: Vec<String>,
+/// A channel on a given source, as specified in modules' configs
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Endpoint {
+    pub source: String,
+    pub channel: String,
 }
 
-impl Command {
-    fn from_msg<'a>(msg: &'a Message) -> Option<Command> {
-        if let MessageContent::Text(txt) = msg.content.clone() {
-            let cmd_char = CONFIG
-                .lock()
-                .ok()
-                .expect("Couldn't lock CONFIG")
-                .custom
-                .command_char
-                .clone();
-            if !txt.starts_with(&cmd_char) {
-                return None;
-            }
-            let text = &txt[cmd_char.len()..];
-            let words = text.split(" ");
-            Some(Command {
-                sender: msg.author.clone(),
-                channel: msg.channel.clone(),
-                params: words.into_iter().map(str::to_owned).collect(),
-            })
-        } else {
-            None
-        }
+impl Endpoint {
+    pub fn send_text(&self, core: &mut CoreAPI, text: String) {
+        core.send(
+            &SourceId(self.source.clone()),
+            Message {
+                author: "".to_owned(),
+                channel: Channel::Channel(self.channel.clone()),
+                content: MessageContent::Text(text),
+            },
+        );
     }
 }
+
+/// The random number generator owned by a module. With a `seed` from the config the module's
+/// choices can be reproduced; without one it is seeded from the system, except in test builds,
+/// which are always deterministic.
+pub fn module_rng(seed: Option<u64>) -> ModuleRng {
+    match seed {
+        Some(seed) => seeded_rng(seed),
+        None if cfg!(test) => seeded_rng(0),
+        None => ModuleRng::from_entropy(),
+    }
+}
+
+/// The generator returned by `module_rng`. A named algorithm, unlike `StdRng`, which may change
+/// between rand releases, so that a seed keeps reproducing the same choices.
+pub type ModuleRng = ChaChaRng;
+
+/// Uses the seed's bytes directly, as the expansion done by `seed_from_u64` isn't guaranteed to
+/// stay the same either
+fn seeded_rng(seed: u64) -> ModuleRng {
+    let mut bytes = [0; 32];
+    for (i, byte) in bytes.iter_mut().take(8).enumerate() {
+        *byte = (seed >> (i * 8)) as u8;
+    }
+    ModuleRng::from_seed(bytes)
+}
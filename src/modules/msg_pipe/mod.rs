@@ -1,15 +1,10 @@
+use modules::Endpoint;
 use toml::Value;
 use universal_chat::{
     Channel, CoreAPI, Event, Message, MessageContent, Module, ResumeEventHandling, SourceEvent,
     SourceId,
 };
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-struct Endpoint {
-    source: String,
-    channel: String,
-}
-
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MsgPipe {
     endpoints: Vec<Endpoint>,
@@ -0,0 +1,317 @@
+//! Dictionaries of the individual authors, so that RandomChat can imitate them
+
+use super::dictionaries::{Dictionaries, Split};
+use super::dictionary::Dictionary;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The file in the directory recording when each author was last learned from, as
+/// "<file name> <unix time>" lines
+const ACTIVITY_FILE: &str = "activity.txt";
+
+/// The per-author dictionaries, as given in the config
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuthorsConfig {
+    /// the directory holding the dictionaries
+    pub path: String,
+    /// the dictionaries of the least recently active authors are deleted beyond this number
+    pub max_authors: Option<usize>,
+    pub order: Option<usize>,
+}
+
+/// A dictionary for each author, identified by the lowercased nick regardless of the source.
+/// They are saved without backups.
+pub struct Authors {
+    dir: String,
+    max_authors: usize,
+    dicts: Dictionaries,
+    /// when each dictionary was last learned into, by path - decay and forgetting change the
+    /// files too, so their modification times don't tell
+    last_learned: HashMap<String, u64>,
+    /// `last_learned` changed since it was last saved
+    activity_dirty: bool,
+}
+
+/// The lowercased nick, with the characters that aren't safe in file names escaped as "%xx"
+fn file_name(nick: &str) -> String {
+    let mut name = String::new();
+    for c in nick.to_lowercase().chars() {
+        if c.is_alphanumeric() || c == '-' || c == '_' {
+            name.push(c);
+        } else {
+            let mut buf = [0; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                name.push_str(&format!("%{:02x}", byte));
+            }
+        }
+    }
+    name.push_str(".dat");
+    name
+}
+
+impl Authors {
+    /// Creates the directory if it doesn't exist
+    pub fn new(
+        dir: String,
+        max_authors: usize,
+        order: usize,
+        tokenizer: String,
+    ) -> io::Result<Authors> {
+        fs::create_dir_all(&dir)?;
+        let mut last_learned = HashMap::new();
+        match fs::File::open(Path::new(&dir).join(ACTIVITY_FILE)) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    let mut parts = line.split_whitespace();
+                    if let (Some(name), Some(Ok(time))) =
+                        (parts.next(), parts.next().map(str::parse))
+                    {
+                        let path = Path::new(&dir).join(name).to_string_lossy().into_owned();
+                        last_learned.insert(path, time);
+                    }
+                }
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        Ok(Authors {
+            // the paths are always given directly, so the default one is never used
+            dicts: Dictionaries::new(dir.clone(), Split::None, Vec::new(), order, tokenizer),
+            dir,
+            max_authors,
+            last_learned,
+            activity_dirty: false,
+        })
+    }
+
+    fn path(&self, nick: &str) -> String {
+        Path::new(&self.dir)
+            .join(file_name(nick))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    pub fn learn(&mut self, nick: &str, line: &str) {
+        if nick.is_empty() {
+            return;
+        }
+        let path = self.path(nick);
+        if let Some(dict) = self.dicts.get_mut(&path) {
+            dict.learn_from_line(line);
+            self.last_learned.insert(path, now());
+            self.activity_dirty = true;
+        }
+    }
+
+    /// The author's dictionary, unless nothing has been learned from them
+    pub fn get(&mut self, nick: &str) -> Option<&Dictionary> {
+        let path = self.path(nick);
+        let known = self
+            .dicts
+            .get(&path)
+            .map_or(false, |dict| dict.num_entries() > 0);
+        if !known {
+            // not kept in memory for whoever is asked about
+            self.dicts.unload(&path);
+            return None;
+        }
+        self.dicts.get(&path)
+    }
+
+    /// Deletes everything learned from the author
+    pub fn remove(&mut self, nick: &str) -> io::Result<()> {
+        let path = self.path(nick);
+        self.dicts.unload(&path);
+        if self.last_learned.remove(&path).is_some() {
+            self.activity_dirty = true;
+        }
+        match fs::remove_file(&path) {
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Changes every author's dictionary with `f`, like `Dictionaries::update_each`, so only the
+    /// changed ones stay loaded. Returns whether any of them changed.
+    pub fn update<F: Fn(&mut Dictionary) -> bool>(&mut self, f: F) -> bool {
+        let mut paths = self.dicts.loaded_paths();
+        match self.saved_paths() {
+            Ok(saved) => paths.extend(saved),
+            Err(err) => eprintln!("RandomChat: listing {} failed: {}", self.dir, err),
+        }
+        paths.sort();
+        paths.dedup();
+        !self.dicts.update_each(paths, f).is_empty()
+    }
+
+    pub fn decay(&mut self, now: u64, half_life: u64) {
+        self.dicts.decay(now, half_life);
+    }
+
+    /// Errors that happened while loading dictionaries since the last call
+    pub fn take_errors(&mut self) -> Vec<String> {
+        self.dicts.take_errors()
+    }
+
+    /// Saves the dictionaries changed since the last save, then deletes those of the least
+    /// recently active authors above `max_authors`
+    pub fn save(&mut self) -> Vec<(String, io::Result<()>)> {
+        let mut results = self.dicts.save(0);
+        if let Err(err) = self.evict() {
+            eprintln!(
+                "RandomChat: removing old dictionaries from {} failed: {}",
+                self.dir, err
+            );
+        }
+        if self.activity_dirty {
+            let path = Path::new(&self.dir).join(ACTIVITY_FILE);
+            let result = self.save_activity(&path);
+            self.activity_dirty = result.is_err();
+            results.push((path.to_string_lossy().into_owned(), result));
+        }
+        results
+    }
+
+    fn save_activity(&self, path: &Path) -> io::Result<()> {
+        let mut lines: Vec<String> = self
+            .last_learned
+            .iter()
+            .filter_map(|(dict_path, time)| {
+                let name = Path::new(dict_path).file_name()?;
+                Some(format!("{} {}\n", name.to_string_lossy(), time))
+            })
+            .collect();
+        lines.sort();
+        // replaced at once, like the opt-outs
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, lines.concat())
+            .and_then(|_| fs::rename(&tmp_path, path))
+            .map_err(|err| {
+                let _ = fs::remove_file(&tmp_path);
+                err
+            })
+    }
+
+    /// The paths of the dictionaries in the directory
+    fn saved_paths(&self) -> io::Result<Vec<String>> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |ext| ext == "dat") {
+                paths.push(path.to_string_lossy().into_owned());
+            }
+        }
+        Ok(paths)
+    }
+
+    /// The dictionaries not in `last_learned`, saved before it existed, count as last learned
+    /// into when they were last modified
+    fn evict(&mut self) -> io::Result<()> {
+        let mut files = Vec::new();
+        for path in self.saved_paths()? {
+            let time = match self.last_learned.get(&path) {
+                Some(&time) => time,
+                None => fs::metadata(&path)?
+                    .modified()?
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |time| time.as_secs()),
+            };
+            files.push((time, path));
+        }
+        if files.len() <= self.max_authors {
+            return Ok(());
+        }
+        // the most recently active first
+        files.sort_by(|a, b| b.cmp(a));
+        for (_, path) in files.drain(self.max_authors..) {
+            self.dicts.unload(&path);
+            self.last_learned.remove(&path);
+            self.activity_dirty = true;
+            fs::remove_file(&path)?;
+        }
+        Ok(())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn temp_dir(name: &str) -> String {
+        let dir = env::temp_dir().join(format!("chatbot-test-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir.to_string_lossy().into_owned()
+    }
+
+    fn open(dir: &str, max_authors: usize) -> Authors {
+        Authors::new(dir.to_owned(), max_authors, 2, "chat".to_owned()).unwrap()
+    }
+
+    #[test]
+    fn activity_is_read_back() {
+        let dir = temp_dir("activity");
+        fs::create_dir_all(&dir).unwrap();
+        let activity = "a.dat 10\nbroken\nb.dat soon\n\nc.dat 30\n";
+        fs::write(Path::new(&dir).join(ACTIVITY_FILE), activity).unwrap();
+        let authors = open(&dir, 10);
+        let mut last_learned: Vec<(String, u64)> = authors
+            .last_learned
+            .iter()
+            .map(|(path, &time)| (path.clone(), time))
+            .collect();
+        last_learned.sort();
+        assert_eq!(
+            last_learned,
+            vec![(authors.path("a"), 10), (authors.path("c"), 30)]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn least_recently_active_are_evicted() {
+        let dir = temp_dir("evict");
+        let mut authors = open(&dir, 2);
+        for &(nick, time) in &[("ann", 100), ("bob", 300), ("cid", 200)] {
+            authors.learn(nick, "hello there");
+            let path = authors.path(nick);
+            authors.last_learned.insert(path, time);
+        }
+        for (path, result) in authors.save() {
+            assert!(result.is_ok(), "{}", path);
+        }
+        assert!(!Path::new(&authors.path("ann")).exists());
+        assert!(authors.get("ann").is_none());
+        assert!(authors.get("bob").is_some());
+        assert!(authors.get("cid").is_some());
+        let activity = fs::read_to_string(Path::new(&dir).join(ACTIVITY_FILE)).unwrap();
+        assert_eq!(activity, "bob.dat 300\ncid.dat 200\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_changed_dictionaries_stay_loaded() {
+        let dir = temp_dir("update");
+        let mut authors = open(&dir, 10);
+        authors.learn("ann", "hello there");
+        authors.learn("bob", "good night");
+        authors.save();
+        let mut authors = open(&dir, 10);
+        assert!(!authors.update(|_| false));
+        assert!(authors.dicts.loaded_paths().is_empty());
+        assert!(authors.update(|dict| dict.forget_line("good night")));
+        assert_eq!(authors.dicts.loaded_paths(), vec![authors.path("bob")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
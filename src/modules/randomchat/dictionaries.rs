@@ -0,0 +1,416 @@
+use super::dictionary::{Dictionary, DictionaryError};
+use super::tokenizer;
+use modules::Endpoint;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use universal_chat::{Channel, SourceId};
+
+/// How the channels not belonging to any configured group are assigned dictionaries
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Split {
+    /// A single dictionary for everything
+    None,
+    /// A dictionary per source
+    Source,
+    /// A dictionary per channel
+    Channel,
+}
+
+/// Sources and channels sharing a dictionary
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DictionaryGroup {
+    pub path: String,
+    #[serde(default)]
+    pub sources: Vec<String>,
+    #[serde(default)]
+    pub channels: Vec<Endpoint>,
+}
+
+/// The dictionaries used by RandomChat, identified by their paths and loaded when first needed
+pub struct Dictionaries {
+    default_path: String,
+    split: Split,
+    groups: Vec<DictionaryGroup>,
+    order: usize,
+    tokenizer: String,
+    loaded: HashMap<String, Dictionary>,
+    /// changed since they were last saved
+    dirty: HashSet<String>,
+    /// failed to load - they aren't used until restart, so that the files aren't overwritten
+    broken: HashSet<String>,
+    errors: Vec<String>,
+}
+
+impl Dictionaries {
+    pub fn new(
+        default_path: String,
+        split: Split,
+        groups: Vec<DictionaryGroup>,
+        order: usize,
+        tokenizer: String,
+    ) -> Dictionaries {
+        Dictionaries {
+            default_path,
+            split,
+            groups,
+            order,
+            tokenizer,
+            loaded: HashMap::new(),
+            dirty: HashSet::new(),
+            broken: HashSet::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn default_path(&self) -> &str {
+        &self.default_path
+    }
+
+    /// The path of the dictionary used for the given channel
+    pub fn path_for(&self, source: &SourceId, channel: &Channel) -> String {
+        let channel_name = match *channel {
+            Channel::Channel(ref name) => Some(name),
+            _ => None,
+        };
+        if let Some(name) = channel_name {
+            let group = self.groups.iter().find(|group| {
+                group
+                    .channels
+                    .iter()
+                    .any(|endpoint| endpoint.source == source.0 && endpoint.channel == *name)
+            });
+            if let Some(group) = group {
+                return group.path.clone();
+            }
+        }
+        if let Some(group) = self
+            .groups
+            .iter()
+            .find(|group| group.sources.contains(&source.0))
+        {
+            return group.path.clone();
+        }
+        match (self.split, channel_name) {
+            (Split::None, _) => self.default_path.clone(),
+            (Split::Source, _) | (Split::Channel, None) => self.split_path(&[&source.0]),
+            (Split::Channel, Some(name)) => self.split_path(&[&source.0, name]),
+        }
+    }
+
+    /// Where the file name and its extension start in the default path
+    fn file_name_parts(&self) -> (usize, usize) {
+        let file_start = self.default_path.rfind('/').map_or(0, |pos| pos + 1);
+        let ext_start = match self.default_path[file_start..].rfind('.') {
+            Some(pos) if pos > 0 => file_start + pos,
+            _ => self.default_path.len(),
+        };
+        (file_start, ext_start)
+    }
+
+    /// dictionary.dat -> dictionary.<part1>.<part2>.dat
+    fn split_path(&self, parts: &[&str]) -> String {
+        let (_, ext_start) = self.file_name_parts();
+        let mut path = self.default_path[..ext_start].to_owned();
+        for part in parts {
+            path.push('.');
+            path.extend(part.chars().map(|c| {
+                if c.is_alphanumeric() || c == '-' || c == '_' || c == '#' {
+                    c
+                } else {
+                    '_'
+                }
+            }));
+        }
+        path.push_str(&self.default_path[ext_start..]);
+        path
+    }
+
+    /// The paths of all the dictionaries: the default one, the groups', the loaded ones and the
+    /// split ones saved next to the default one, including those of channels not seen lately
+    pub fn known_paths(&self) -> Vec<String> {
+        let mut paths = vec![self.default_path.clone()];
+        paths.extend(self.groups.iter().map(|group| group.path.clone()));
+        paths.extend(self.loaded.keys().cloned());
+        let (file_start, ext_start) = self.file_name_parts();
+        let dir = &self.default_path[..file_start];
+        let prefix = format!("{}.", &self.default_path[file_start..ext_start]);
+        let ext = &self.default_path[ext_start..];
+        match fs::read_dir(if dir.is_empty() { "." } else { dir }) {
+            Ok(entries) => {
+                for entry in entries.filter_map(Result::ok) {
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    // not backups or temporary files, which are named on after the extension
+                    let backup = name
+                        .rsplit('.')
+                        .next()
+                        .map_or(false, |last| last == "tmp" || last.parse::<usize>().is_ok());
+                    if name.starts_with(&prefix) && name.ends_with(ext) && !backup {
+                        paths.push(format!("{}{}", dir, name));
+                    }
+                }
+            }
+            Err(err) => eprintln!("RandomChat: listing the dictionaries failed: {}", err),
+        }
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    fn load(&self, path: &str) -> Result<Dictionary, DictionaryError> {
+        let mut dict = match Dictionary::load(path) {
+            Ok(dict) => dict,
+            Err(DictionaryError::Io(ref err)) if err.kind() == io::ErrorKind::NotFound => {
+                Dictionary::new(self.order)
+            }
+            Err(err) => return Err(err),
+        };
+        if dict.order() != self.order {
+            return Err(DictionaryError::OrderMismatch {
+                expected: self.order,
+                actual: dict.order(),
+            });
+        }
+        dict.set_tokenizer(tokenizer::from_name(&self.tokenizer).unwrap());
+        Ok(dict)
+    }
+
+    /// Loads the dictionary if it isn't loaded yet. Returns an error only the first time a
+    /// dictionary fails to load, `Ok(false)` afterwards.
+    fn ensure_loaded(&mut self, path: &str) -> Result<bool, DictionaryError> {
+        if self.broken.contains(path) {
+            return Ok(false);
+        }
+        if !self.loaded.contains_key(path) {
+            match self.load(path) {
+                Ok(dict) => {
+                    self.loaded.insert(path.to_owned(), dict);
+                }
+                Err(err) => {
+                    self.broken.insert(path.to_owned());
+                    return Err(err);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Returns the dictionary at `path`, unless it failed to load, in which case the error can be
+    /// retrieved with `take_errors`
+    pub fn get(&mut self, path: &str) -> Option<&Dictionary> {
+        match self.ensure_loaded(path) {
+            Ok(true) => self.loaded.get(path),
+            Ok(false) => None,
+            Err(err) => {
+                self.errors.push(format!(
+                    "loading {} failed, it won't be used until restart: {}",
+                    path, err
+                ));
+                None
+            }
+        }
+    }
+
+    /// Like `get`, but marks the dictionary as needing a save
+    pub fn get_mut(&mut self, path: &str) -> Option<&mut Dictionary> {
+        if self.get(path).is_none() {
+            return None;
+        }
+        self.dirty.insert(path.to_owned());
+        self.loaded.get_mut(path)
+    }
+
+    /// Changes the dictionary with `f`, which returns whether it changed anything, so that an
+    /// unchanged dictionary isn't saved again. `None` if the dictionary can't be used.
+    pub fn update<F: FnOnce(&mut Dictionary) -> bool>(&mut self, path: &str, f: F) -> Option<bool> {
+        self.get(path)?;
+        let changed = f(self.loaded.get_mut(path).unwrap());
+        if changed {
+            self.dirty.insert(path.to_owned());
+        }
+        Some(changed)
+    }
+
+    /// Changes the dictionaries at `paths` with `f`, like `update`. The ones that weren't loaded
+    /// before and haven't changed are unloaded again. Returns the paths of the changed ones.
+    pub fn update_each<F: Fn(&mut Dictionary) -> bool>(
+        &mut self,
+        paths: Vec<String>,
+        f: F,
+    ) -> Vec<String> {
+        let mut changed = Vec::new();
+        for path in paths {
+            let loaded = self.loaded.contains_key(&path);
+            match self.update(&path, &f) {
+                Some(true) => changed.push(path),
+                Some(false) if !loaded => self.unload(&path),
+                _ => (),
+            }
+        }
+        changed
+    }
+
+    /// The paths of the loaded dictionaries
+    pub fn loaded_paths(&self) -> Vec<String> {
+        self.loaded.keys().cloned().collect()
+    }
+
+    /// Drops the dictionary from memory, along with the changes that haven't been saved
+    pub fn unload(&mut self, path: &str) {
+        self.loaded.remove(path);
+        self.dirty.remove(path);
+    }
+
+    /// Errors that happened while loading dictionaries since the last call
+    pub fn take_errors(&mut self) -> Vec<String> {
+        self.errors.drain(..).collect()
+    }
+
+    /// Decays the loaded dictionaries, see `Dictionary::decay`. The others catch up when they
+    /// are loaded and decayed next time.
+    pub fn decay(&mut self, now: u64, half_life: u64) {
+        for (path, dict) in &mut self.loaded {
+            let decayed_at = dict.decayed_at();
+            dict.decay(now, half_life);
+            if dict.decayed_at() != decayed_at {
+                self.dirty.insert(path.clone());
+            }
+        }
+    }
+
+    /// Saves the dictionaries changed since the last save, returns the result for each of them
+    pub fn save(&mut self, backups: usize) -> Vec<(String, io::Result<()>)> {
+        let mut results = Vec::new();
+        for path in self.dirty.drain() {
+            let result = self
+                .loaded
+                .get_mut(&path)
+                .unwrap()
+                .save_with_backups(&path, backups);
+            results.push((path, result));
+        }
+        // try again next time
+        for &(ref path, ref result) in &results {
+            if result.is_err() {
+                self.dirty.insert(path.clone());
+            }
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::Path;
+    use std::process;
+
+    fn with_split(split: Split) -> Dictionaries {
+        let groups = vec![
+            DictionaryGroup {
+                path: "data/rust.dat".to_owned(),
+                sources: Vec::new(),
+                channels: vec![Endpoint {
+                    source: "irc".to_owned(),
+                    channel: "#rust".to_owned(),
+                }],
+            },
+            DictionaryGroup {
+                path: "data/discord.dat".to_owned(),
+                sources: vec!["discord".to_owned(), "irc".to_owned()],
+                channels: Vec::new(),
+            },
+        ];
+        Dictionaries::new(
+            "data/dictionary.dat".to_owned(),
+            split,
+            groups,
+            2,
+            "chat".to_owned(),
+        )
+    }
+
+    fn path(dicts: &Dictionaries, source: &str, channel: Channel) -> String {
+        dicts.path_for(&SourceId(source.to_owned()), &channel)
+    }
+
+    fn channel(name: &str) -> Channel {
+        Channel::Channel(name.to_owned())
+    }
+
+    #[test]
+    fn groups_come_before_the_split() {
+        let dicts = with_split(Split::Channel);
+        // a channel of a group before the group of its source
+        assert_eq!(path(&dicts, "irc", channel("#rust")), "data/rust.dat");
+        assert_eq!(path(&dicts, "irc", channel("#chat")), "data/discord.dat");
+        assert_eq!(
+            path(&dicts, "slack", channel("#rust")),
+            "data/dictionary.slack.#rust.dat"
+        );
+        assert_eq!(
+            path(&dicts, "slack", Channel::User("someone".to_owned())),
+            "data/dictionary.slack.dat"
+        );
+        let dicts = with_split(Split::Source);
+        assert_eq!(
+            path(&dicts, "slack", channel("#rust")),
+            "data/dictionary.slack.dat"
+        );
+        let dicts = with_split(Split::None);
+        assert_eq!(
+            path(&dicts, "slack", channel("#rust")),
+            "data/dictionary.dat"
+        );
+        assert_eq!(path(&dicts, "irc", channel("#rust")), "data/rust.dat");
+    }
+
+    #[test]
+    fn dictionaries_of_another_order_are_not_used() {
+        let path = env::temp_dir().join(format!("chatbot-test-{}-order.dat", process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let mut dict = Dictionary::new(3);
+        dict.learn_from_line("the cat sat on the mat");
+        dict.save(&path).unwrap();
+        let mut dicts =
+            Dictionaries::new(path.clone(), Split::None, Vec::new(), 2, "chat".to_owned());
+        match dicts.load(&path) {
+            Err(DictionaryError::OrderMismatch {
+                expected: 2,
+                actual: 3,
+            }) => {}
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("loaded a dictionary of another order"),
+        }
+        assert!(dicts.get(&path).is_none());
+        assert_eq!(dicts.take_errors().len(), 1);
+        // reported once
+        assert!(dicts.get(&path).is_none());
+        assert!(dicts.take_errors().is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn channel_names_stay_in_the_directory() {
+        let dicts = with_split(Split::Channel);
+        for &(name, escaped) in &[
+            ("#chan", "data/dictionary.slack.#chan.dat"),
+            ("#a/b", "data/dictionary.slack.#a_b.dat"),
+            ("..", "data/dictionary.slack.__.dat"),
+            (
+                "../../etc/passwd",
+                "data/dictionary.slack.______etc_passwd.dat",
+            ),
+            ("/etc/passwd", "data/dictionary.slack._etc_passwd.dat"),
+        ] {
+            let path = path(&dicts, "slack", channel(name));
+            assert_eq!(path, escaped);
+            assert_eq!(Path::new(&path).parent(), Some(Path::new("data")));
+        }
+        assert_eq!(
+            path(&dicts, "a/../b", channel("#c")),
+            "data/dictionary.a____b.#c.dat"
+        );
+    }
+}
@@ -1,10 +1,36 @@
-use rand::{self, Rng};
-use std::collections::{BTreeMap, HashMap};
-use std::fs::File;
-use std::io::{self, Read, Write};
-use std::path::Path;
+use super::frozen::{self, Data, Frozen, Source};
+use super::successors::Successors;
+use super::tokenizer::{Tokenizer, WhitespaceTokenizer};
+use super::words::{cmp_ignore_case, WordTable};
+use rand::Rng;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
 
-fn to_4u8(x: u32) -> [u8; 4] {
+/// Marks dictionary files that start with a header. Files written before the header was
+/// introduced start directly with the number of words.
+pub const MAGIC: &[u8; 4] = b"CBMD";
+/// Version history:
+/// 1 - header with the order of the chain
+/// 2 - CRC32 of the whole file appended at the end
+/// 3 - layout that can be used without reading the file into memory, see `frozen`
+/// 4 - hashes of the learned lines
+/// 5 - time of the last decay
+pub const FORMAT_VERSION: u32 = 5;
+
+/// The order used by dictionaries saved without a header
+pub const DEFAULT_ORDER: usize = 2;
+pub const MAX_ORDER: usize = 4;
+/// The most tokens a single walk of the chain takes, punctuation included, so that a cycle of
+/// tokens which aren't words - always taken at temperature 0 - can't go on forever
+const MAX_WALK_TOKENS: usize = 1000;
+
+pub fn to_4u8(x: u32) -> [u8; 4] {
     let mut result = [0; 4];
     result[0] = (x & 0xFF) as u8;
     result[1] = ((x >> 8) & 0xFF) as u8;
@@ -13,13 +39,157 @@ fn to_4u8(x: u32) -> [u8; 4] {
     result
 }
 
-fn to_u32(x: &[u8]) -> Option<u32> {
+pub fn to_u32(x: &[u8]) -> Option<u32> {
     if x.len() < 4 {
         return None;
     }
     Some(x[0] as u32 + ((x[1] as u32) << 8) + ((x[2] as u32) << 16) + ((x[3] as u32) << 24))
 }
 
+/// `path` with `.suffix` appended, e.g. dictionary.dat -> dictionary.dat.1
+fn with_suffix<S: AsRef<str>>(path: &Path, suffix: S) -> PathBuf {
+    let mut result = OsString::from(path);
+    result.push(".");
+    result.push(suffix.as_ref());
+    PathBuf::from(result)
+}
+
+/// Shifts `path.1` .. `path.(count - 1)` by one, dropping the oldest one, and preserves the
+/// current contents of `path` as `path.1`
+fn rotate_backups(path: &Path, count: usize) -> io::Result<()> {
+    for i in (1..count).rev() {
+        let backup = with_suffix(path, i.to_string());
+        if backup.exists() {
+            fs::rename(&backup, with_suffix(path, (i + 1).to_string()))?;
+        }
+    }
+    let newest = with_suffix(path, "1");
+    if newest.exists() {
+        fs::remove_file(&newest)?;
+    }
+    // a hard link keeps the live file in place until the new version replaces it
+    if fs::hard_link(path, &newest).is_err() {
+        fs::copy(path, &newest)?;
+    }
+    Ok(())
+}
+
+lazy_static! {
+    static ref CRC32_TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut c = i as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 {
+                    0xEDB8_8320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+            }
+            *entry = c;
+        }
+        table
+    };
+}
+
+/// CRC-32 (IEEE 802.3), the same as used by zip and png, computed incrementally
+pub struct Crc32(u32);
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        Crc32(0xFFFF_FFFF)
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = CRC32_TABLE[((self.0 ^ byte as u32) & 0xFF) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        self.0 ^ 0xFFFF_FFFF
+    }
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+    crc.finish()
+}
+
+#[derive(Debug)]
+pub enum DictionaryError {
+    Io(io::Error),
+    /// The data ended in the middle of a value
+    Truncated,
+    UnsupportedVersion(u32),
+    InvalidOrder(u32),
+    /// Dictionaries of different orders can't be combined
+    OrderMismatch {
+        expected: usize,
+        actual: usize,
+    },
+    ChecksumMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    /// Unknown tag of a serialised `Word`
+    InvalidWord(u8),
+    /// A `Word::Word` referring to a word that isn't in the dictionary
+    InvalidWordIndex(u32),
+    InvalidUtf8,
+    /// The parts of the file don't fit together
+    Corrupted(&'static str),
+}
+
+impl fmt::Display for DictionaryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DictionaryError::Io(ref err) => write!(f, "I/O error: {}", err),
+            DictionaryError::Truncated => write!(f, "unexpected end of data"),
+            DictionaryError::UnsupportedVersion(version) => {
+                write!(f, "unsupported format version {}", version)
+            }
+            DictionaryError::InvalidOrder(order) => write!(f, "invalid chain order {}", order),
+            DictionaryError::OrderMismatch { expected, actual } => write!(
+                f,
+                "expected a dictionary of order {}, got order {}",
+                expected, actual
+            ),
+            DictionaryError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "checksum mismatch: expected {:08x}, got {:08x}",
+                expected, actual
+            ),
+            DictionaryError::InvalidWord(tag) => write!(f, "invalid word tag {:#x}", tag),
+            DictionaryError::InvalidWordIndex(index) => {
+                write!(f, "word index {} out of range", index)
+            }
+            DictionaryError::InvalidUtf8 => write!(f, "word is not valid UTF-8"),
+            DictionaryError::Corrupted(reason) => write!(f, "corrupted data: {}", reason),
+        }
+    }
+}
+
+impl Error for DictionaryError {
+    fn description(&self) -> &str {
+        "dictionary error"
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            DictionaryError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DictionaryError {
+    fn from(err: io::Error) -> DictionaryError {
+        DictionaryError::Io(err)
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Word {
     Start1,
@@ -29,35 +199,13 @@ pub enum Word {
 }
 
 impl Word {
-    pub fn into_bytes(&self) -> [u8; 5] {
-        let mut result = [0; 5];
-        match *self {
-            Word::Start1 => {
-                result[0] = 1;
-            }
-            Word::Start2 => {
-                result[0] = 2;
-            }
-            Word::End => {
-                result[0] = 0xFF;
-            }
-            Word::Word(i) => {
-                let i_bytes = to_4u8(i);
-                for j in 0..4 {
-                    result[j + 1] = i_bytes[j];
-                }
-            }
-        }
-        result
-    }
-
     pub fn from_bytes(bytes: &[u8]) -> Option<Word> {
         if bytes.len() < 5 {
             return None;
         }
 
         match bytes[0] {
-            0 => Some(Word::Word(to_u32(&bytes[1..5]).unwrap())),
+            0 => to_u32(&bytes[1..5]).map(Word::Word),
             1 => Some(Word::Start1),
             2 => Some(Word::Start2),
             0xFF => Some(Word::End),
@@ -66,7 +214,69 @@ impl Word {
     }
 }
 
-pub type Entry = (Word, Word);
+/// How the sentinel words are written when a dictionary is shown to a human
+const SENTINEL_NAMES: [(Word, &str); 3] = [
+    (Word::Start1, "<start1>"),
+    (Word::Start2, "<start2>"),
+    (Word::End, "<end>"),
+];
+
+/// `Word`s as stored in the transition tables: words as their indices, and the sentinels as
+/// the highest values
+pub const FIRST_SENTINEL: u32 = 0xFFFF_FFFD;
+const PACKED_START1: u32 = FIRST_SENTINEL;
+const PACKED_START2: u32 = FIRST_SENTINEL + 1;
+const PACKED_END: u32 = FIRST_SENTINEL + 2;
+
+fn pack(word: Word) -> u32 {
+    match word {
+        Word::Start1 => PACKED_START1,
+        Word::Start2 => PACKED_START2,
+        Word::End => PACKED_END,
+        Word::Word(index) => index,
+    }
+}
+
+fn unpack(word: u32) -> Word {
+    match word {
+        PACKED_START1 => Word::Start1,
+        PACKED_START2 => Word::Start2,
+        PACKED_END => Word::End,
+        index => Word::Word(index),
+    }
+}
+
+/// The last `order` words of a sentence, used to choose the next one
+pub type Entry = Vec<Word>;
+
+/// An entry as stored in the transition tables: the packed words, followed by zeros if the
+/// order is lower than `MAX_ORDER`
+pub type Key = [u32; MAX_ORDER];
+
+/// Adds the successors from `a` and `b` into `out`, summing the counts of the common words
+fn merge_successors(a: &[(u32, u32)], b: &[(u32, u32)], out: &mut Vec<(u32, u32)>) {
+    out.clear();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].0.cmp(&b[j].0) {
+            Ordering::Less => {
+                out.push(a[i]);
+                i += 1;
+            }
+            Ordering::Greater => {
+                out.push(b[j]);
+                j += 1;
+            }
+            Ordering::Equal => {
+                out.push((a[i].0, a[i].1.saturating_add(b[j].1)));
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    out.extend_from_slice(&a[i..]);
+    out.extend_from_slice(&b[j..]);
+}
 
 struct ByteReader<'a> {
     bytes: &'a [u8],
@@ -74,229 +284,1664 @@ struct ByteReader<'a> {
 }
 
 impl<'a> ByteReader<'a> {
-    fn new(bytes: &[u8]) -> ByteReader {
+    fn new(bytes: &'a [u8]) -> ByteReader<'a> {
         ByteReader {
             bytes: bytes,
             cursor: 0,
         }
     }
 
-    fn read_u32(&mut self) -> u32 {
-        let result = to_u32(&self.bytes[self.cursor..self.cursor + 4]).unwrap();
-        self.cursor += 4;
-        result
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], DictionaryError> {
+        if self.bytes.len() - self.cursor < len {
+            return Err(DictionaryError::Truncated);
+        }
+        let result = &self.bytes[self.cursor..self.cursor + len];
+        self.cursor += len;
+        Ok(result)
     }
 
-    fn read_word(&mut self) -> Word {
-        let result = Word::from_bytes(&self.bytes[self.cursor..self.cursor + 5]).unwrap();
-        self.cursor += 5;
-        result
+    fn read_u32(&mut self) -> Result<u32, DictionaryError> {
+        self.read_bytes(4).map(|bytes| to_u32(bytes).unwrap())
     }
 
-    fn read_string(&mut self) -> String {
-        let word_length = to_u32(&self.bytes[self.cursor..self.cursor + 4]).unwrap() as usize;
-        self.cursor += 4;
-        let word =
-            ::std::str::from_utf8(&self.bytes[self.cursor..self.cursor + word_length]).unwrap();
-        self.cursor += word_length;
-        word.to_string()
+    fn read_word(&mut self) -> Result<Word, DictionaryError> {
+        let bytes = self.read_bytes(5)?;
+        Word::from_bytes(bytes).ok_or(DictionaryError::InvalidWord(bytes[0]))
+    }
+
+    fn read_entry(&mut self, order: usize) -> Result<Entry, DictionaryError> {
+        (0..order).map(|_| self.read_word()).collect()
+    }
+
+    fn read_string(&mut self) -> Result<String, DictionaryError> {
+        let word_length = self.read_u32()? as usize;
+        let bytes = self.read_bytes(word_length)?;
+        ::std::str::from_utf8(bytes)
+            .map(str::to_owned)
+            .map_err(|_| DictionaryError::InvalidUtf8)
+    }
+}
+
+/// FNV-1a hash of the lowercased tokens of a line, used to recognise generated sentences
+/// copying a learned line. Unlike `DefaultHasher`, it is guaranteed not to change, so it can
+/// be saved.
+fn line_hash<'a, I: IntoIterator<Item = &'a str>>(tokens: I) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    let mut add = |byte: u8| {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    };
+    for token in tokens {
+        let mut buf = [0; 4];
+        for c in token.chars().flat_map(char::to_lowercase) {
+            for &byte in c.encode_utf8(&mut buf).as_bytes() {
+                add(byte);
+            }
+        }
+        // never a part of valid UTF-8
+        add(0xFF);
+    }
+    hash
+}
+
+/// What a generated sentence has to satisfy
+#[derive(Clone, Copy, Debug)]
+pub struct Quality {
+    /// the minimum and maximum number of words, not counting punctuation
+    pub min_words: usize,
+    pub max_words: usize,
+    /// how many more sentences to try if the first one doesn't qualify
+    pub retries: usize,
+    /// whether to reject sentences identical to a learned line
+    pub reject_copies: bool,
+}
+
+/// How the next word is chosen from the ones that followed the previous words
+#[derive(Clone, Copy, Debug)]
+pub struct Sampling {
+    /// the weights of the words are their counts raised to the power of `1 / temperature`:
+    /// below 1 the common words are preferred even more, above 1 the rare ones get closer to
+    /// them, and 0 always chooses the most common word
+    pub temperature: f64,
+    /// if not 0, only this many of the most common words are considered
+    pub top_k: usize,
+}
+
+impl Sampling {
+    /// Choosing the words in proportion to their counts
+    pub fn new() -> Sampling {
+        Sampling {
+            temperature: 1.0,
+            top_k: 0,
+        }
+    }
+
+    /// Whether the counts are used as they are
+    fn is_plain(&self) -> bool {
+        self.temperature == 1.0 && self.top_k == 0
     }
 }
 
 pub struct Dictionary {
-    words: Vec<String>,
-    index_map: HashMap<String, usize>,
-    dict: HashMap<Entry, BTreeMap<Word, u32>>,
+    order: usize,
+    /// what has been loaded from a file in the current format, used without copying it into
+    /// memory
+    base: Option<Frozen>,
+    /// the words learned since `base` was loaded, their indices follow the ones in `base`
+    words: WordTable,
+    /// the transitions learned since `base` was loaded
+    dict: HashMap<Key, Successors>,
+    // the same transitions read backwards: (w2, ..., wn+1) -> w1
+    rev_dict: HashMap<Key, Successors>,
+    /// the keys of `rev_dict` by their first word, where walks around a keyword start
+    rev_by_word: HashMap<u32, Vec<Key>>,
+    /// the hashes of the lines learned since `base` was loaded
+    lines: HashSet<u64>,
+    /// when the counts were last halved by `decay`, in seconds since the Unix epoch, 0 if never
+    decayed_at: u64,
+    tokenizer: Box<Tokenizer + Send>,
 }
 
 impl Dictionary {
-    #[allow(unused)]
-    pub fn new() -> Dictionary {
+    pub fn new(order: usize) -> Dictionary {
+        assert!(
+            order >= 1 && order <= MAX_ORDER,
+            "Markov chain order must be between 1 and {}",
+            MAX_ORDER
+        );
         Dictionary {
-            words: Vec::new(),
-            index_map: HashMap::new(),
+            order,
+            base: None,
+            words: WordTable::new(),
             dict: HashMap::new(),
+            rev_dict: HashMap::new(),
+            rev_by_word: HashMap::new(),
+            lines: HashSet::new(),
+            decayed_at: 0,
+            tokenizer: Box::new(WhitespaceTokenizer),
         }
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut result = Vec::new();
-        // push number of words
-        result.extend_from_slice(&to_4u8(self.words.len() as u32));
-        // push each word preceded by its length
-        for word in (&self.words).into_iter() {
-            let bytes = word.as_bytes();
-            result.extend_from_slice(&to_4u8(bytes.len() as u32));
-            result.extend_from_slice(bytes);
+    pub fn order(&self) -> usize {
+        self.order
+    }
+
+    /// Sets how lines are split into words when learning, and how they are joined back when
+    /// generating. Splitting on whitespace is the default.
+    pub fn set_tokenizer(&mut self, tokenizer: Box<Tokenizer + Send>) {
+        self.tokenizer = tokenizer;
+    }
+
+    /// Reads the formats older than the one in `frozen`
+    fn from_bytes(bytes: &[u8]) -> Result<Dictionary, DictionaryError> {
+        if !bytes.starts_with(MAGIC) {
+            // written before the header was introduced - no checksum and a fixed order
+            return Dictionary::read_contents(&mut ByteReader::new(bytes), DEFAULT_ORDER);
         }
-        // write dict
-        // first, the number of entries
-        result.extend_from_slice(&to_4u8(self.dict.len() as u32));
-        // now the entries
-        for key in self.dict.keys() {
-            // first, the key
-            result.extend_from_slice(&key.0.into_bytes());
-            result.extend_from_slice(&key.1.into_bytes());
-            // second, possible results
-            let data = &self.dict[key];
-            // btreemap length
-            result.extend_from_slice(&to_4u8(data.len() as u32));
-            // and entries
-            for (word, chance) in data {
-                result.extend_from_slice(&word.into_bytes());
-                result.extend_from_slice(&to_4u8(*chance));
+        let version = ByteReader::new(&bytes[MAGIC.len()..]).read_u32()?;
+        let data = match version {
+            1 => bytes,
+            2 => {
+                if bytes.len() < MAGIC.len() + 12 {
+                    return Err(DictionaryError::Truncated);
+                }
+                let (data, trailer) = bytes.split_at(bytes.len() - 4);
+                let expected = ByteReader::new(trailer).read_u32()?;
+                let actual = crc32(data);
+                if expected != actual {
+                    return Err(DictionaryError::ChecksumMismatch { expected, actual });
+                }
+                data
             }
+            _ => return Err(DictionaryError::UnsupportedVersion(version)),
+        };
+        let mut reader = ByteReader::new(&data[MAGIC.len() + 4..]);
+        let order = reader.read_u32()?;
+        if order < 1 || order as usize > MAX_ORDER {
+            return Err(DictionaryError::InvalidOrder(order));
         }
-
-        result
+        Dictionary::read_contents(&mut reader, order as usize)
     }
 
-    fn from_bytes(bytes: &[u8]) -> Option<Dictionary> {
-        let mut reader = ByteReader::new(bytes);
-        let num_words = reader.read_u32();
-        let mut words = Vec::new();
-        let mut index_map = HashMap::new();
+    fn read_contents(reader: &mut ByteReader, order: usize) -> Result<Dictionary, DictionaryError> {
+        let mut dict = Dictionary::new(order);
+        let num_words = reader.read_u32()?;
         // read words
-        for i in 0..num_words {
-            let word = reader.read_string();
-            words.push(word.clone());
-            index_map.insert(word.to_lowercase(), i as usize);
+        for _ in 0..num_words {
+            let word = reader.read_string()?;
+            dict.words.push(&word);
         }
+        let check_word = |word: Word| match word {
+            Word::Word(index) if index >= num_words => {
+                Err(DictionaryError::InvalidWordIndex(index))
+            }
+            word => Ok(pack(word)),
+        };
         // read entry map
-        let num_entries = reader.read_u32();
-        let mut hashmap = HashMap::new();
+        let num_entries = reader.read_u32()?;
         for _ in 0..num_entries {
-            // first entry word
-            let word1 = reader.read_word();
-            // second entry word
-            let word2 = reader.read_word();
-            let num_results = reader.read_u32();
-            let mut results = BTreeMap::new();
+            let mut key = [0; MAX_ORDER];
+            for (i, word) in reader.read_entry(order)?.into_iter().enumerate() {
+                key[i] = check_word(word)?;
+            }
+            let num_results = reader.read_u32()?;
             for _ in 0..num_results {
-                let word = reader.read_word();
-                let chance = reader.read_u32();
-                results.insert(word, chance);
+                let word = check_word(reader.read_word()?)?;
+                let chance = reader.read_u32()?;
+                dict.add_packed(key, word, chance);
+            }
+        }
+        Ok(dict)
+    }
+
+    fn reverse_transitions(
+        dict: &HashMap<Key, Successors>,
+        order: usize,
+    ) -> HashMap<Key, Successors> {
+        let mut rev_dict = HashMap::new();
+        for (key, data) in dict {
+            for &(next, chance) in data.iter() {
+                Dictionary::add_transition(
+                    &mut rev_dict,
+                    Dictionary::reverse_key(key, next, order),
+                    key[0],
+                    chance,
+                );
             }
-            hashmap.insert((word1, word2), results);
         }
-        Some(Dictionary {
-            words: words,
-            index_map: index_map,
-            dict: hashmap,
-        })
+        rev_dict
+    }
+
+    fn set_rev_dict(&mut self, rev_dict: HashMap<Key, Successors>) {
+        let mut rev_by_word = HashMap::new();
+        for key in rev_dict.keys() {
+            rev_by_word
+                .entry(key[0])
+                .or_insert_with(Vec::new)
+                .push(*key);
+        }
+        self.rev_dict = rev_dict;
+        self.rev_by_word = rev_by_word;
+    }
+
+    /// (w1, ..., wn), w -> (w2, ..., wn, w)
+    fn reverse_key(key: &Key, next: u32, order: usize) -> Key {
+        let mut rev_key = [0; MAX_ORDER];
+        rev_key[..order - 1].copy_from_slice(&key[1..order]);
+        rev_key[order - 1] = next;
+        rev_key
+    }
+
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.save_with_backups(path, 0)
     }
 
-    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
-        let mut file = File::create(path)?;
-        let bytes = self.to_bytes();
-        file.write_all(&bytes)?;
+    /// Saves the dictionary, keeping up to `backups` previous versions of the file as
+    /// `<path>.1` (the newest) to `<path>.<backups>` (the oldest).
+    ///
+    /// The data is written to a temporary file first and renamed into place, so a failed save
+    /// leaves the previous version intact. Afterwards the dictionary is used from the new file,
+    /// so that what has been learned doesn't have to be kept in memory anymore.
+    pub fn save_with_backups<P: AsRef<Path>>(&mut self, path: P, backups: usize) -> io::Result<()> {
+        let path = path.as_ref();
+        let tmp_path = with_suffix(path, "tmp");
+        let result = File::create(&tmp_path)
+            .and_then(|file| {
+                frozen::write(&*self, &file)?;
+                file.sync_all()
+            })
+            .and_then(|_| {
+                if backups > 0 && path.exists() {
+                    rotate_backups(path, backups)?;
+                }
+                fs::rename(&tmp_path, path)
+            });
+        if let Err(err) = result {
+            // the file at `path` is left as it was
+            let _ = fs::remove_file(&tmp_path);
+            return Err(err);
+        }
+        // make sure the rename itself survives a crash
+        #[cfg(unix)]
+        {
+            let dir = match path.parent() {
+                Some(dir) if dir != Path::new("") => dir,
+                _ => Path::new("."),
+            };
+            File::open(dir)?.sync_all()?;
+        }
+        // written just now from a valid dictionary, so only the layout needs checking
+        let base = Frozen::written(Data::open(path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        self.base = Some(base);
+        self.words = WordTable::new();
+        self.dict = HashMap::new();
+        self.set_rev_dict(HashMap::new());
+        self.lines = HashSet::new();
         Ok(())
     }
 
-    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Dictionary> {
-        let mut file = File::open(path)?;
-        let mut bytes = Vec::new();
-        file.read_to_end(&mut bytes)?;
-        if let Some(dict) = Dictionary::from_bytes(&bytes) {
-            Ok(dict)
-        } else {
-            Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "Invalid dictionary input",
-            ))
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Dictionary, DictionaryError> {
+        let data = Data::open(path.as_ref())?;
+        if !frozen::is_frozen(&data) {
+            return Dictionary::from_bytes(&data);
         }
+        let base = Frozen::new(data)?;
+        let mut dict = Dictionary::new(base.order());
+        dict.decayed_at = base.decayed_at();
+        dict.base = Some(base);
+        Ok(dict)
     }
 
-    fn insert_word<S: AsRef<str>>(&mut self, word: S) -> usize {
-        if let Some(index) = self.index_map.get(&word.as_ref().to_lowercase()) {
-            return *index;
+    /// Checks the checksum of the file the dictionary was loaded from, see `Frozen::verify`.
+    /// Loading only makes sure that the file can be used safely.
+    pub fn verify(&self) -> Result<(), DictionaryError> {
+        self.base.as_ref().map_or(Ok(()), Frozen::verify)
+    }
+
+    fn base_words(&self) -> u32 {
+        self.base.as_ref().map_or(0, |base| base.num_words() as u32)
+    }
+
+    fn find_index(&self, word: &str) -> Option<u32> {
+        if let Some(index) = self.base.as_ref().and_then(|base| base.find_word(word)) {
+            return Some(index);
         }
-        self.words.push(word.as_ref().to_string());
-        self.index_map.insert(
-            word.as_ref().to_lowercase().to_string(),
-            self.words.len() - 1,
-        );
-        self.words.len() - 1
+        self.words.find(word).map(|index| self.base_words() + index)
+    }
+
+    fn insert_word(&mut self, word: &str) -> u32 {
+        if let Some(index) = self.find_index(word) {
+            return index;
+        }
+        let index = self.base_words() + self.words.push(word);
+        assert!(index < FIRST_SENTINEL, "Too many words in the dictionary");
+        index
+    }
+
+    /// Adds a word to the dictionary, unless it is already known
+    pub fn add_word<S: AsRef<str>>(&mut self, word: S) -> Word {
+        Word::Word(self.insert_word(word.as_ref()))
+    }
+
+    /// Adds everything `other` has learned to this dictionary
+    pub fn merge(&mut self, other: &Dictionary) -> Result<(), DictionaryError> {
+        if other.order != self.order {
+            return Err(DictionaryError::OrderMismatch {
+                expected: self.order,
+                actual: other.order,
+            });
+        }
+        // the same word usually has a different index in the other dictionary
+        let words: Vec<u32> = (0..other.num_words() as u32)
+            .map(|index| self.insert_word(other.word_str(Word::Word(index))))
+            .collect();
+        let remap = |word: u32| {
+            if word < FIRST_SENTINEL {
+                words[word as usize]
+            } else {
+                word
+            }
+        };
+        let order = self.order;
+        other.for_each_entry(false, &mut |key, successors| {
+            let mut new_key = [0; MAX_ORDER];
+            for (new_word, &word) in new_key.iter_mut().zip(&key[..order]) {
+                *new_word = remap(word);
+            }
+            for &(word, count) in successors {
+                self.add_packed(new_key, remap(word), count);
+            }
+        });
+        self.lines.extend(other.line_hashes());
+        Ok(())
     }
 
     pub fn learn_from_line<S: AsRef<str>>(&mut self, line: S) {
-        let words = line.as_ref().split_whitespace();
-        let mut words_new = vec![Word::Start1, Word::Start2];
-        words_new.extend(words.map(|x| Word::Word(self.insert_word(x) as u32)));
+        let words = match self.tokenizer.tokenize(line.as_ref()) {
+            Some(words) => words,
+            None => return,
+        };
+        if words.is_empty() {
+            return;
+        }
+        self.lines
+            .insert(line_hash(words.iter().map(String::as_str)));
+        let mut words_new = self.start_entry();
+        words_new.extend(words.iter().map(|x| Word::Word(self.insert_word(x))));
         words_new.push(Word::End);
 
-        for window in words_new.windows(3) {
-            let entry = (window[0], window[1]);
-            let word = window[2];
-            if let Some(data) = self.dict.get_mut(&entry) {
-                if let Some(chance) = data.get_mut(&word) {
-                    *chance += 1;
-                    continue;
-                }
-                data.insert(word, 1);
-                continue;
+        let order = self.order;
+        for window in words_new.windows(order + 1) {
+            self.add_transitions(window[..order].to_vec(), window[order], 1);
+        }
+    }
+
+    /// Whether a line with the same words has been learned
+    pub fn has_learned<S: AsRef<str>>(&self, line: S) -> bool {
+        match self.tokenizer.tokenize(line.as_ref()) {
+            Some(ref words) if !words.is_empty() => {
+                self.knows_line_hash(line_hash(words.iter().map(String::as_str)))
             }
-            let mut map = BTreeMap::new();
-            map.insert(word, 1);
-            self.dict.insert(entry, map);
+            _ => false,
         }
     }
 
-    fn get_next_word(&self, w1: Word, w2: Word) -> Option<Word> {
-        let mut rng = rand::thread_rng();
-        let possibilities;
-        if let Some(p) = self.dict.get(&(w1, w2)) {
-            possibilities = p;
-        } else {
+    /// Undoes learning the line once: the counts of its transitions are decreased, the
+    /// transitions whose counts drop to zero are removed, and so are the words no longer used.
+    /// Nothing is changed unless the whole line could have been learned. Returns whether it was
+    /// forgotten.
+    pub fn forget_line<S: AsRef<str>>(&mut self, line: S) -> bool {
+        let words = match self.tokenizer.tokenize(line.as_ref()) {
+            Some(words) => words,
+            None => return false,
+        };
+        if words.is_empty() {
+            return false;
+        }
+        let mut entry = self.start_entry();
+        for word in &words {
+            match self.find_index(word) {
+                Some(index) => entry.push(Word::Word(index)),
+                None => return false,
+            }
+        }
+        entry.push(Word::End);
+        // the same transition can appear in a line more than once
+        let order = self.order;
+        let mut transitions = HashMap::new();
+        for window in entry.windows(order + 1) {
+            let key = self.pack_entry(&window[..order]);
+            *transitions.entry((key, pack(window[order]))).or_insert(0) += 1;
+        }
+        let mut successors = Vec::new();
+        for (&(key, word), &count) in &transitions {
+            self.merged_successors(false, &key, &mut successors);
+            match successors.binary_search_by_key(&word, |&(next, _)| next) {
+                Ok(pos) if successors[pos].1 >= count => (),
+                _ => return false,
+            }
+        }
+
+        self.thaw();
+        // the line could have been learned more than once, it's only gone if one of its
+        // transitions is
+        let mut removed = false;
+        for ((key, word), count) in transitions {
+            let data = self.dict.get_mut(&key).unwrap();
+            let decreased = data
+                .to_sorted()
+                .into_iter()
+                .map(|(next, next_count)| {
+                    (
+                        next,
+                        if next == word {
+                            next_count - count
+                        } else {
+                            next_count
+                        },
+                    )
+                })
+                .filter(|&(_, next_count)| next_count > 0)
+                .collect();
+            let decreased = Successors::from_sorted(decreased);
+            removed |= decreased.len() < data.len();
+            *data = decreased;
+        }
+        if removed {
+            self.lines
+                .remove(&line_hash(words.iter().map(String::as_str)));
+        }
+        self.remove_dangling();
+        self.compact();
+        true
+    }
+
+    /// Removes the word, everything it appears in and the words no longer used afterwards.
+    /// The sentinels can't be removed. Returns whether the word was known.
+    pub fn forget_word(&mut self, word: &str) -> bool {
+        let removed = match self.find_index(word) {
+            Some(index) => self.remove_word(Word::Word(index)),
+            None => return false,
+        };
+        self.compact();
+        removed
+    }
+
+    /// Records `count` more occurrences of `word` following `entry`
+    pub fn add_transitions(&mut self, entry: Entry, word: Word, count: u32) {
+        let key = self.pack_entry(&entry);
+        self.add_packed(key, pack(word), count);
+    }
+
+    fn add_packed(&mut self, key: Key, word: u32, count: u32) {
+        if count == 0 {
+            return;
+        }
+        let rev_key = Dictionary::reverse_key(&key, word, self.order);
+        if !self.rev_dict.contains_key(&rev_key) {
+            self.rev_by_word
+                .entry(rev_key[0])
+                .or_insert_with(Vec::new)
+                .push(rev_key);
+        }
+        Dictionary::add_transition(&mut self.rev_dict, rev_key, key[0], count);
+        Dictionary::add_transition(&mut self.dict, key, word, count);
+    }
+
+    fn pack_entry(&self, entry: &[Word]) -> Key {
+        assert_eq!(entry.len(), self.order, "Entry length must match the order");
+        let mut key = [0; MAX_ORDER];
+        for (i, &word) in entry.iter().enumerate() {
+            key[i] = pack(word);
+        }
+        key
+    }
+
+    fn unpack_entry(&self, key: &Key) -> Entry {
+        key[..self.order].iter().map(|&word| unpack(word)).collect()
+    }
+
+    /// The entry preceding the first word of every sentence: `Start1`s followed by a `Start2`
+    fn start_entry(&self) -> Entry {
+        let mut entry = vec![Word::Start1; self.order - 1];
+        entry.push(Word::Start2);
+        entry
+    }
+
+    fn add_transition(dict: &mut HashMap<Key, Successors>, key: Key, word: u32, count: u32) {
+        dict.entry(key)
+            .or_insert_with(Successors::new)
+            .add(word, count);
+    }
+
+    fn get_next_word<R: Rng>(
+        &self,
+        entry: &[Word],
+        sampling: &Sampling,
+        rng: &mut R,
+    ) -> Option<Word> {
+        self.choose_word(false, entry, sampling, rng)
+    }
+
+    fn get_prev_word<R: Rng>(
+        &self,
+        entry: &[Word],
+        sampling: &Sampling,
+        rng: &mut R,
+    ) -> Option<Word> {
+        self.choose_word(true, entry, sampling, rng)
+    }
+
+    fn choose_word<R: Rng>(
+        &self,
+        reverse: bool,
+        entry: &[Word],
+        sampling: &Sampling,
+        rng: &mut R,
+    ) -> Option<Word> {
+        let key = self.pack_entry(entry);
+        if !sampling.is_plain() {
+            return self.choose_reweighted(reverse, &key, sampling, rng);
+        }
+        let base = self.base.as_ref().and_then(|base| {
+            let table = base.table(reverse);
+            table.find(&key).map(|index| (table, index))
+        });
+        let base_total = base.map_or(0, |(table, index)| table.total(index) as u64);
+        let dict = if reverse { &self.rev_dict } else { &self.dict };
+        let possibilities = dict.get(&key);
+        let sum = base_total + possibilities.map_or(0, |data| data.total());
+        if sum == 0 {
             return None;
         }
-        let mut sum = 0;
-        for (_, v) in possibilities.iter() {
-            sum += *v;
+
+        let random = rng.gen_range(0, sum);
+        match base {
+            Some((table, index)) if random < base_total => {
+                Some(unpack(table.sample(index, random as u32)))
+            }
+            _ => possibilities.map(|data| unpack(data.sample(random - base_total))),
         }
+    }
 
-        let mut random = rng.gen_range(0, sum);
-        for (&k, &v) in possibilities.iter() {
-            if random < v {
-                return Some(k);
+    /// Chooses the word with the weights changed according to `sampling`. Unlike
+    /// `choose_word`, this has to look at all the successors.
+    fn choose_reweighted<R: Rng>(
+        &self,
+        reverse: bool,
+        key: &Key,
+        sampling: &Sampling,
+        rng: &mut R,
+    ) -> Option<Word> {
+        let mut successors = Vec::new();
+        self.merged_successors(reverse, key, &mut successors);
+        if sampling.top_k > 0 && successors.len() > sampling.top_k {
+            // the sort is stable, so equal counts stay in a consistent order
+            successors.sort_by(|a, b| b.1.cmp(&a.1));
+            successors.truncate(sampling.top_k);
+        }
+        let max = successors.iter().map(|&(_, count)| count).max()?;
+        if sampling.temperature == 0.0 {
+            let most_common: Vec<u32> = successors
+                .iter()
+                .filter(|&&(_, count)| count == max)
+                .map(|&(word, _)| word)
+                .collect();
+            return rng.choose(&most_common).map(|&word| unpack(word));
+        }
+        // relative to the most common word, so that big exponents don't overflow
+        let exponent = 1.0 / sampling.temperature;
+        let weights: Vec<f64> = successors
+            .iter()
+            .map(|&(_, count)| (count as f64 / max as f64).powf(exponent))
+            .collect();
+        let mut random = rng.gen::<f64>() * weights.iter().sum::<f64>();
+        for (&(word, _), weight) in successors.iter().zip(weights) {
+            if random < weight {
+                return Some(unpack(word));
             }
-            random -= v;
+            random -= weight;
         }
+        // rounding errors can leave a tiny remainder
+        successors.last().map(|&(word, _)| unpack(word))
+    }
 
+    /// Generates a sentence satisfying `quality`, around a keyword taken from `text` if it is
+    /// given. Returns `None` if none of the attempts succeeded. All the random choices are made
+    /// with `rng`, so the same sentence is generated again from an RNG in the same state.
+    pub fn generate<R: Rng>(
+        &self,
+        text: Option<&str>,
+        quality: &Quality,
+        sampling: &Sampling,
+        rng: &mut R,
+    ) -> Option<String> {
+        for _ in 0..quality.retries + 1 {
+            let words = match text {
+                Some(text) => self.generate_around(text, quality.max_words, sampling, rng),
+                None => self.generate_words(quality.max_words, sampling, rng),
+            };
+            let words = match words {
+                Some(words) => words,
+                None => continue,
+            };
+            let num_words = words.iter().filter(|&&word| self.is_word(word)).count();
+            if num_words == 0 || num_words < quality.min_words {
+                continue;
+            }
+            if quality.reject_copies && self.is_copy(&words) {
+                continue;
+            }
+            return Some(self.join_words(&words));
+        }
         None
     }
 
-    pub fn generate_sentence(&self) -> String {
-        let mut w1 = Word::Start1;
-        let mut w2 = Word::Start2;
+    /// A random walk from the start of a sentence, `None` if it has more than `max_words`
+    /// words
+    fn generate_words<R: Rng>(
+        &self,
+        max_words: usize,
+        sampling: &Sampling,
+        rng: &mut R,
+    ) -> Option<Vec<Word>> {
+        self.generate_forward(self.start_entry(), max_words, sampling, rng)
+    }
+
+    /// Generates a sentence around a keyword taken from `text`, growing it both backwards and
+    /// forwards from the keyword. Falls back to `generate_words` if none of the words in `text`
+    /// is known.
+    fn generate_around<R: Rng>(
+        &self,
+        text: &str,
+        max_words: usize,
+        sampling: &Sampling,
+        rng: &mut R,
+    ) -> Option<Vec<Word>> {
+        let keyword = match self.choose_keyword(text, rng) {
+            Some(keyword) => pack(keyword),
+            None => return self.generate_words(max_words, sampling, rng),
+        };
+        // find the entries starting with the keyword, so that we can walk in both directions
+        let mut entries = self.rev_by_word.get(&keyword).cloned().unwrap_or_default();
+        if let Some(ref base) = self.base {
+            let table = base.table(true);
+            entries.extend(table.starting_with(keyword).map(|index| table.key(index)));
+        }
+        entries.sort();
+        entries.dedup();
+        let entry = match rng.choose(&entries) {
+            Some(key) => self.unpack_entry(key),
+            None => return self.generate_words(max_words, sampling, rng),
+        };
+
+        let entry_words = entry.iter().filter(|&&word| self.is_word(word)).count();
+        let mut words = self.generate_backward(
+            entry.clone(),
+            max_words.checked_sub(entry_words)?,
+            sampling,
+            rng,
+        )?;
+        let backward_words = words.iter().filter(|&&word| self.is_word(word)).count();
+        words.reverse();
+        words.extend_from_slice(&entry);
+        words.extend(self.generate_forward(
+            entry,
+            max_words - entry_words - backward_words,
+            sampling,
+            rng,
+        )?);
+        Some(words)
+    }
+
+    /// Whether the word counts towards the length of a sentence, as opposed to punctuation and
+    /// the sentinels
+    fn is_word(&self, word: Word) -> bool {
+        match word {
+            Word::Word(_) => self.word_str(word).chars().any(char::is_alphanumeric),
+            _ => false,
+        }
+    }
+
+    /// Whether the words are the same as a learned line
+    fn is_copy(&self, words: &[Word]) -> bool {
+        let hash = line_hash(
+            words
+                .iter()
+                .filter(|word| match **word {
+                    Word::Word(_) => true,
+                    _ => false,
+                })
+                .map(|&word| self.word_str(word)),
+        );
+        self.knows_line_hash(hash)
+    }
+
+    fn knows_line_hash(&self, hash: u64) -> bool {
+        self.lines.contains(&hash)
+            || self
+                .base
+                .as_ref()
+                .map_or(false, |base| base.contains_line(hash))
+    }
+
+    fn choose_keyword<S: AsRef<str>, R: Rng>(&self, text: S, rng: &mut R) -> Option<Word> {
+        let known: Vec<u32> = self
+            .tokenizer
+            .tokenize(text.as_ref())
+            .unwrap_or_default()
+            .iter()
+            .filter(|word| word.chars().any(char::is_alphanumeric))
+            .filter_map(|word| self.find_index(word))
+            .collect();
+        // prefer longer words, the short ones are usually too common to be meaningful
+        let long: Vec<u32> = known
+            .iter()
+            .filter(|&&index| self.word_str(Word::Word(index)).chars().count() > 3)
+            .cloned()
+            .collect();
+        let candidates = if long.is_empty() { known } else { long };
+        rng.choose(&candidates).map(|&index| Word::Word(index))
+    }
 
+    /// Walks the chain forwards, starting after `entry`. Gives up after more than `max_words`
+    /// words or `MAX_WALK_TOKENS` tokens.
+    fn generate_forward<R: Rng>(
+        &self,
+        mut entry: Entry,
+        mut max_words: usize,
+        sampling: &Sampling,
+        rng: &mut R,
+    ) -> Option<Vec<Word>> {
         let mut words = Vec::new();
-        loop {
-            let next_word;
-            if let Some(nw) = self.get_next_word(w1, w2) {
-                next_word = nw;
-            } else {
+        while let Some(next_word) = self.get_next_word(&entry, sampling, rng) {
+            if next_word == Word::End {
                 break;
             }
-            if next_word == Word::End {
+            if self.is_word(next_word) {
+                max_words = max_words.checked_sub(1)?;
+            }
+            if words.len() == MAX_WALK_TOKENS {
+                return None;
+            }
+            words.push(next_word);
+            entry.remove(0);
+            entry.push(next_word);
+        }
+        Some(words)
+    }
+
+    /// Walks the chain backwards, starting before `entry`. Gives up after more than
+    /// `max_words` words or `MAX_WALK_TOKENS` tokens.
+    /// The words are returned in the order they were generated, i.e. reversed.
+    fn generate_backward<R: Rng>(
+        &self,
+        mut entry: Entry,
+        mut max_words: usize,
+        sampling: &Sampling,
+        rng: &mut R,
+    ) -> Option<Vec<Word>> {
+        let mut words = Vec::new();
+        while let Some(prev_word) = self.get_prev_word(&entry, sampling, rng) {
+            if prev_word == Word::Start1 || prev_word == Word::Start2 {
                 break;
             }
-            if let Word::Word(index) = next_word {
-                words.push(self.words[index as usize].clone());
+            if self.is_word(prev_word) {
+                max_words = max_words.checked_sub(1)?;
+            }
+            if words.len() == MAX_WALK_TOKENS {
+                return None;
+            }
+            words.push(prev_word);
+            entry.pop();
+            entry.insert(0, prev_word);
+        }
+        Some(words)
+    }
+
+    fn join_words(&self, words: &[Word]) -> String {
+        let words: Vec<&str> = words
+            .iter()
+            .filter_map(|&word| match word {
+                Word::Word(_) => Some(self.word_str(word)),
+                _ => None,
+            })
+            .collect();
+        self.tokenizer.join(&words)
+    }
+
+    pub fn num_words(&self) -> usize {
+        self.base_words() as usize + self.words.len()
+    }
+
+    pub fn num_entries(&self) -> usize {
+        match self.base {
+            Some(ref base) => {
+                let table = base.table(false);
+                let new = self
+                    .dict
+                    .keys()
+                    .filter(|key| table.find(key).is_none())
+                    .count();
+                table.len() + new
+            }
+            None => self.dict.len(),
+        }
+    }
+
+    /// The hashes of the learned lines, sorted
+    pub fn line_hashes(&self) -> Vec<u64> {
+        let mut hashes: Vec<u64> = self.lines.iter().cloned().collect();
+        if let Some(ref base) = self.base {
+            hashes.extend(base.line_hashes());
+        }
+        hashes.sort();
+        hashes.dedup();
+        hashes
+    }
+
+    /// Marks the lines with the given hashes as learned
+    pub fn add_line_hashes<I: IntoIterator<Item = u64>>(&mut self, hashes: I) {
+        self.lines.extend(hashes);
+    }
+
+    pub fn entries(&self) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        self.for_each_entry(false, &mut |key, _| {
+            entries.push(self.unpack_entry(key));
+        });
+        entries
+    }
+
+    /// Calls `f` with every transition in the dictionary: entry, next word and count
+    pub fn for_each_transition<F: FnMut(&[Word], Word, u32)>(&self, mut f: F) {
+        self.for_each_entry(false, &mut |key, successors| {
+            let entry = self.unpack_entry(key);
+            for &(word, count) in successors {
+                f(&entry, unpack(word), count);
+            }
+        });
+    }
+
+    /// The words that can follow `entry`, with their counts
+    pub fn successors(&self, entry: &[Word]) -> Option<Vec<(Word, u32)>> {
+        let key = self.pack_entry(entry);
+        let mut successors = Vec::new();
+        self.merged_successors(false, &key, &mut successors);
+        if successors.is_empty() {
+            return None;
+        }
+        Some(
+            successors
+                .into_iter()
+                .map(|(word, count)| (unpack(word), count))
+                .collect(),
+        )
+    }
+
+    /// The packed words following (or preceding, if `reverse`) the entry in both the base and
+    /// the new transitions, sorted by the word
+    fn merged_successors(&self, reverse: bool, key: &Key, out: &mut Vec<(u32, u32)>) {
+        let mut base_successors = Vec::new();
+        if let Some(ref base) = self.base {
+            let table = base.table(reverse);
+            if let Some(index) = table.find(key) {
+                table.successors(index, &mut base_successors);
+            }
+        }
+        let dict = if reverse { &self.rev_dict } else { &self.dict };
+        let new_successors = dict.get(key).map_or_else(Vec::new, Successors::to_sorted);
+        merge_successors(&base_successors, &new_successors, out);
+    }
+
+    /// Looks up a word, case-insensitively. The sentinels can be given by their names.
+    pub fn find_word(&self, word: &str) -> Option<Word> {
+        if let Some(&(sentinel, _)) = SENTINEL_NAMES.iter().find(|&&(_, name)| name == word) {
+            return Some(sentinel);
+        }
+        self.find_index(word).map(Word::Word)
+    }
+
+    pub fn word_str(&self, word: Word) -> &str {
+        match word {
+            Word::Word(index) => match self.base {
+                Some(ref base) if (index as usize) < base.num_words() => base.word(index),
+                _ => self.words.get(index - self.base_words()),
+            },
+            sentinel => SENTINEL_NAMES
+                .iter()
+                .find(|&&(word, _)| word == sentinel)
+                .map(|&(_, name)| name)
+                .unwrap(),
+        }
+    }
+
+    /// Copies what has been loaded from the file into memory, so that it can be modified
+    fn thaw(&mut self) {
+        if self.base.is_none() {
+            return;
+        }
+        let mut words = WordTable::new();
+        for index in 0..self.num_words() as u32 {
+            words.push(self.word_str(Word::Word(index)));
+        }
+        let mut dict = HashMap::new();
+        self.for_each_entry(false, &mut |key, successors| {
+            dict.insert(*key, Successors::from_sorted(successors.to_vec()));
+        });
+        let mut rev_dict = HashMap::new();
+        self.for_each_entry(true, &mut |key, successors| {
+            rev_dict.insert(*key, Successors::from_sorted(successors.to_vec()));
+        });
+        self.lines.extend(self.base.as_ref().unwrap().line_hashes());
+        self.base = None;
+        self.words = words;
+        self.dict = dict;
+        self.set_rev_dict(rev_dict);
+    }
+
+    /// Removes the entry and the transitions leading to it. Returns whether it existed.
+    pub fn remove_entry(&mut self, entry: &[Word]) -> bool {
+        self.thaw();
+        let key = self.pack_entry(entry);
+        if self.dict.remove(&key).is_none() {
+            return false;
+        }
+        // a transition from (w1, ..., wn) to w leads to (w2, ..., wn, w)
+        let order = self.order;
+        let last = key[order - 1];
+        for (other, data) in &mut self.dict {
+            if other[1..order] == key[..order - 1] {
+                data.retain(|word, _| word != last);
             }
-            w1 = w2;
-            w2 = next_word;
         }
+        self.remove_dangling();
+        true
+    }
+
+    /// Removes the word and every entry or transition it appears in. Returns whether it
+    /// existed. The dictionary should be compacted afterwards.
+    pub fn remove_word(&mut self, word: Word) -> bool {
+        self.thaw();
+        let word = pack(word);
+        let order = self.order;
+        let before = self.dict.len();
+        self.dict.retain(|key, _| !key[..order].contains(&word));
+        let mut removed = self.dict.len() != before;
+        for data in self.dict.values_mut() {
+            let before = data.len();
+            data.retain(|next, _| next != word);
+            removed |= data.len() != before;
+        }
+        self.remove_dangling();
+        removed
+    }
+
+    /// Removes transitions seen fewer than `min_count` times, returns how many were removed.
+    /// The dictionary should be compacted afterwards.
+    pub fn prune(&mut self, min_count: u32) -> usize {
+        self.thaw();
+        let mut removed = 0;
+        for data in self.dict.values_mut() {
+            let before = data.len();
+            data.retain(|_, count| count >= min_count);
+            removed += before - data.len();
+        }
+        self.remove_dangling();
+        removed
+    }
 
-        if !words.is_empty() {
-            let mut result = words[0].to_string();
-            for word in &words[1..] {
-                result.push_str(" ");
-                result.push_str(word);
+    /// Halves all the counts once for every `half_life` seconds that passed between the last
+    /// decay and `now`, removing the transitions whose counts drop to zero and the words that
+    /// aren't used anymore. A dictionary that has never been decayed only starts counting the
+    /// time. Returns how many times the counts were halved.
+    ///
+    /// Halving copies what has been loaded from the file into memory, like any other removal,
+    /// which costs about as much as learning the whole dictionary again, so it's only done once
+    /// a whole `half_life` has passed. The hashes of the learned lines are kept, as there's no
+    /// telling which of them the remaining transitions came from; they take 8 bytes a line.
+    pub fn decay(&mut self, now: u64, half_life: u64) -> u64 {
+        assert!(half_life > 0, "the half-life must be positive");
+        if self.decayed_at == 0 {
+            self.decayed_at = now;
+            return 0;
+        }
+        let halvings = now.saturating_sub(self.decayed_at) / half_life;
+        if halvings == 0 {
+            return 0;
+        }
+        // the remainder counts towards the next halving
+        self.decayed_at += halvings * half_life;
+        self.thaw();
+        let shift = halvings.min(32) as u32;
+        for data in self.dict.values_mut() {
+            let halved = data
+                .to_sorted()
+                .into_iter()
+                .map(|(word, count)| (word, count.checked_shr(shift).unwrap_or(0)))
+                .filter(|&(_, count)| count > 0)
+                .collect();
+            *data = Successors::from_sorted(halved);
+        }
+        self.remove_dangling();
+        self.compact();
+        halvings
+    }
+
+    /// The time of the last decay, in seconds since the Unix epoch, 0 if the dictionary has
+    /// never been decayed
+    pub fn decayed_at(&self) -> u64 {
+        self.decayed_at
+    }
+
+    pub fn set_decayed_at(&mut self, time: u64) {
+        self.decayed_at = time;
+    }
+
+    /// Removes entries without any transitions and rebuilds the reverse transitions after
+    /// transitions have been removed
+    fn remove_dangling(&mut self) {
+        self.dict.retain(|_, data| !data.is_empty());
+        let rev_dict = Dictionary::reverse_transitions(&self.dict, self.order);
+        self.set_rev_dict(rev_dict);
+    }
+
+    /// Drops the words no longer used by any entry and renumbers the rest. Returns the number
+    /// of words removed.
+    pub fn compact(&mut self) -> usize {
+        self.thaw();
+        let order = self.order;
+        let mut used = vec![false; self.words.len()];
+        for (key, data) in &self.dict {
+            let next_words = data.iter().map(|&(word, _)| word);
+            for word in key[..order].iter().cloned().chain(next_words) {
+                if word < FIRST_SENTINEL {
+                    used[word as usize] = true;
+                }
+            }
+        }
+        let mut new_indices = vec![0; self.words.len()];
+        let mut words = WordTable::new();
+        for index in 0..self.words.len() {
+            if used[index] {
+                new_indices[index] = words.push(self.words.get(index as u32));
             }
-            result
-        } else {
-            String::new()
         }
+        let removed = used.len() - words.len();
+        let remap = |word: u32| {
+            if word < FIRST_SENTINEL {
+                new_indices[word as usize]
+            } else {
+                word
+            }
+        };
+        self.dict = self
+            .dict
+            .drain()
+            .map(|(mut key, mut data)| {
+                for word in key[..order].iter_mut() {
+                    *word = remap(*word);
+                }
+                // the words keep their relative order
+                data.map_words(&remap);
+                (key, data)
+            })
+            .collect();
+        self.words = words;
+        let rev_dict = Dictionary::reverse_transitions(&self.dict, order);
+        self.set_rev_dict(rev_dict);
+        removed
+    }
+}
+
+impl Source for Dictionary {
+    fn order(&self) -> usize {
+        self.order
+    }
+
+    fn num_words(&self) -> usize {
+        Dictionary::num_words(self)
+    }
+
+    fn word(&self, index: u32) -> &str {
+        self.word_str(Word::Word(index))
+    }
+
+    fn sorted_words(&self) -> Vec<u32> {
+        let base_words = self.base_words();
+        let mut new: Vec<u32> = (0..self.words.len() as u32).collect();
+        new.sort_by(|&a, &b| cmp_ignore_case(self.words.get(a), self.words.get(b)));
+        let base = self
+            .base
+            .as_ref()
+            .map_or_else(Vec::new, |base| base.sorted_words());
+        // merge the two, the words from `base` first among the equal ones
+        let mut sorted = Vec::with_capacity(base.len() + new.len());
+        let (mut i, mut j) = (0, 0);
+        while i < base.len() && j < new.len() {
+            let base_word = self.word_str(Word::Word(base[i]));
+            if cmp_ignore_case(base_word, self.words.get(new[j])) != Ordering::Greater {
+                sorted.push(base[i]);
+                i += 1;
+            } else {
+                sorted.push(base_words + new[j]);
+                j += 1;
+            }
+        }
+        sorted.extend_from_slice(&base[i..]);
+        sorted.extend(new[j..].iter().map(|&index| base_words + index));
+        sorted
+    }
+
+    fn line_hashes(&self) -> Vec<u64> {
+        Dictionary::line_hashes(self)
+    }
+
+    fn decayed_at(&self) -> u64 {
+        self.decayed_at
+    }
+
+    fn for_each_entry(&self, reverse: bool, f: &mut FnMut(&Key, &[(u32, u32)])) {
+        let dict = if reverse { &self.rev_dict } else { &self.dict };
+        let mut keys: Vec<&Key> = dict.keys().collect();
+        keys.sort();
+        let mut keys = keys.into_iter().peekable();
+        let mut new_successors = Vec::new();
+        if let Some(ref base) = self.base {
+            let table = base.table(reverse);
+            let mut base_successors = Vec::new();
+            let mut merged = Vec::new();
+            for index in 0..table.len() {
+                let key = table.key(index);
+                // the entries learned since, preceding this one
+                while let Some(new_key) = keys.peek().cloned() {
+                    if *new_key >= key {
+                        break;
+                    }
+                    dict[new_key].sorted_into(&mut new_successors);
+                    f(new_key, &new_successors);
+                    keys.next();
+                }
+                table.successors(index, &mut base_successors);
+                if keys.peek() == Some(&&key) {
+                    dict[&key].sorted_into(&mut new_successors);
+                    merge_successors(&base_successors, &new_successors, &mut merged);
+                    f(&key, &merged);
+                    keys.next();
+                } else {
+                    f(&key, &base_successors);
+                }
+            }
+        }
+        for key in keys {
+            dict[key].sorted_into(&mut new_successors);
+            f(key, &new_successors);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use modules::module_rng;
+    use std::env;
+    use std::process;
+
+    const QUALITY: Quality = Quality {
+        min_words: 1,
+        max_words: 30,
+        retries: 2,
+        reject_copies: false,
+    };
+
+    fn learned() -> Dictionary {
+        let mut dict = Dictionary::new(2);
+        dict.learn_from_line("the cat sat on the mat");
+        dict.learn_from_line("the dog sat on the cat");
+        dict.learn_from_line("a cat and a dog, on the mat!");
+        dict.learn_from_line("the mat is where the dog sleeps");
+        dict
+    }
+
+    fn transitions(dict: &Dictionary) -> Vec<String> {
+        let mut transitions = Vec::new();
+        dict.for_each_transition(|entry, word, count| {
+            let entry: Vec<&str> = entry.iter().map(|&word| dict.word_str(word)).collect();
+            transitions.push(format!("{:?} {} {}", entry, dict.word_str(word), count));
+        });
+        transitions.sort();
+        transitions
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("chatbot-test-{}-{}.dat", process::id(), name))
+    }
+
+    #[test]
+    fn greedy_walk_through_punctuation_ends() {
+        let mut dict = Dictionary::new(2);
+        dict.learn_from_line("hmm ... ... ... ...");
+        let sampling = Sampling {
+            temperature: 0.0,
+            top_k: 0,
+        };
+        let mut rng = module_rng(None);
+        assert_eq!(dict.generate(None, &QUALITY, &sampling, &mut rng), None);
+        let sampling = Sampling {
+            temperature: 1.0,
+            top_k: 1,
+        };
+        assert_eq!(dict.generate(None, &QUALITY, &sampling, &mut rng), None);
+    }
+
+    #[test]
+    fn seeded_sentences_stay_the_same() {
+        let dict = learned();
+        let sampling = Sampling {
+            temperature: 1.0,
+            top_k: 0,
+        };
+        let mut rng = module_rng(Some(42));
+        let sentences: Vec<Option<String>> = (0..4)
+            .map(|_| dict.generate(None, &QUALITY, &sampling, &mut rng))
+            .collect();
+        // changes only if the generator or the way it's used to choose does
+        let expected = vec![
+            "a cat and a dog, on the mat",
+            "the dog sat on the cat sat on the mat",
+            "the cat sat on the mat!",
+            "the mat is where the dog sat on the cat sat on the mat!",
+        ];
+        let expected: Vec<Option<String>> =
+            expected.into_iter().map(|s| Some(s.to_owned())).collect();
+        assert_eq!(sentences, expected);
+    }
+
+    #[test]
+    fn replies_are_built_around_the_keyword() {
+        let path = temp_path("keyword");
+        let mut dict = learned();
+        dict.save(&path).unwrap();
+        // partly in the file, partly in memory
+        dict.learn_from_line("my parrot sat on the fence");
+        let sampling = Sampling {
+            temperature: 1.0,
+            top_k: 0,
+        };
+        let mut rng = module_rng(None);
+        for &keyword in &["dog", "parrot"] {
+            for _ in 0..10 {
+                let sentence = dict.generate(Some(keyword), &QUALITY, &sampling, &mut rng);
+                assert!(sentence.unwrap().contains(keyword));
+            }
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn saved_dictionary_loads_the_same() {
+        let path = temp_path("round-trip");
+        let mut dict = learned();
+        let expected = transitions(&dict);
+        dict.save(&path).unwrap();
+        // used from the file after saving
+        assert_eq!(transitions(&dict), expected);
+        let loaded = Dictionary::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(loaded.verify().is_ok());
+        assert_eq!(loaded.order(), 2);
+        assert_eq!(transitions(&loaded), expected);
+    }
+
+    #[test]
+    fn lowest_and_highest_orders_work() {
+        let sampling = Sampling {
+            temperature: 1.0,
+            top_k: 0,
+        };
+        for &order in &[1, MAX_ORDER] {
+            let path = temp_path(&format!("order{}", order));
+            let mut dict = Dictionary::new(order);
+            for line in &["the cat sat on the mat", "the dog sat on the cat"] {
+                dict.learn_from_line(line);
+            }
+            let expected = transitions(&dict);
+            let generate = |dict: &Dictionary| {
+                let mut rng = module_rng(Some(7));
+                let sentences: Vec<String> = (0..5)
+                    .map(|_| dict.generate(None, &QUALITY, &sampling, &mut rng).unwrap())
+                    .collect();
+                let reply = dict.generate(Some("dog"), &QUALITY, &sampling, &mut rng);
+                assert!(reply.unwrap().contains("dog"), "order {}", order);
+                sentences
+            };
+            let sentences = generate(&dict);
+            dict.save(&path).unwrap();
+            let loaded = Dictionary::load(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+            assert_eq!(loaded.order(), order);
+            assert_eq!(transitions(&loaded), expected);
+            assert_eq!(generate(&loaded), sentences, "order {}", order);
+        }
+    }
+
+    #[test]
+    fn damaged_files_are_rejected() {
+        let path = temp_path("damaged");
+        learned().save(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+
+        fs::write(&path, &bytes[..bytes.len() - 10]).unwrap();
+        match Dictionary::load(&path) {
+            Err(DictionaryError::Truncated) => {}
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("a truncated file was loaded"),
+        }
+
+        let mut damaged = bytes.clone();
+        let middle = damaged.len() / 2;
+        damaged[middle] ^= 0xff;
+        fs::write(&path, &damaged).unwrap();
+        assert!(Dictionary::load(&path).is_err());
+
+        // a changed word can't make anything panic, only the checksum tells
+        let mut damaged = bytes.clone();
+        let pos = damaged
+            .windows(3)
+            .position(|window| window == b"dog")
+            .unwrap();
+        damaged[pos] = b'h';
+        fs::write(&path, &damaged).unwrap();
+        let loaded = Dictionary::load(&path);
+        fs::remove_file(&path).unwrap();
+        match loaded.map(|dict| dict.verify()) {
+            Ok(Err(DictionaryError::ChecksumMismatch { .. })) => {}
+            Ok(Err(err)) => panic!("unexpected error: {}", err),
+            Ok(Ok(())) => panic!("a damaged file was verified"),
+            Err(err) => panic!("loading failed: {}", err),
+        }
+    }
+
+    #[test]
+    fn corrupted_structure_fails_to_load() {
+        let path = temp_path("corrupted");
+        learned().save(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let field = |pos: usize| to_u32(&bytes[pos..]).unwrap() as usize;
+        // see the layout in frozen.rs, the header of the current version has 12 fields
+        let (num_words, arena_len, forward_entries) = (field(12), field(16), field(20));
+        let offsets = 4 * 12;
+        let arena = offsets + (num_words + 1) * 4 + num_words * 4;
+        let forward = arena + (arena_len + 3) / 4 * 4;
+        // the entries of order 2 are the words and the index of the first transition
+        let transitions = forward + forward_entries * (2 + 1) * 4;
+        let damages = [
+            // a word ending past the arena
+            (offsets + 4, 0xffff),
+            // the first entry's transitions starting past the end of the table
+            (forward + 4 * 2, 0xffff),
+            // a transition to a word that doesn't exist
+            (transitions, num_words as u32),
+        ];
+        for &(pos, value) in &damages {
+            let mut damaged = bytes.clone();
+            damaged[pos..pos + 4].copy_from_slice(&to_4u8(value));
+            fs::write(&path, &damaged).unwrap();
+            if Dictionary::load(&path).is_ok() {
+                panic!("loaded with {} at {}", value, pos);
+            }
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn lines_learned_twice_are_forgotten_twice() {
+        let mut dict = learned();
+        let before = transitions(&dict);
+        dict.learn_from_line("the cat sat on the mat");
+        assert!(dict.forget_line("the cat sat on the mat"));
+        assert_eq!(transitions(&dict), before);
+        assert!(dict.forget_line("the cat sat on the mat"));
+        assert!(!dict.forget_line("the cat sat on the mat"));
+    }
+
+    #[test]
+    fn partly_learned_lines_are_not_forgotten() {
+        let mut dict = learned();
+        let before = transitions(&dict);
+        // an unknown word
+        assert!(!dict.forget_line("the cat sat on the sofa"));
+        // known words, but the line never ended there
+        assert!(!dict.forget_line("the cat sat"));
+        assert_eq!(transitions(&dict), before);
+    }
+
+    #[test]
+    fn lines_are_forgotten_from_the_file() {
+        let path = temp_path("forget");
+        let mut dict = learned();
+        dict.save(&path).unwrap();
+        let mut loaded = Dictionary::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(loaded.forget_line("the mat is where the dog sleeps"));
+        assert!(!loaded.forget_line("the mat is where the dog sleeps"));
+        assert!(loaded.find_word("sleeps").is_none());
+        assert!(loaded.find_word("mat").is_some());
+    }
+
+    #[test]
+    fn merging_sums_the_counts_of_the_same_words() {
+        let mut dict = Dictionary::new(2);
+        dict.learn_from_line("the cat sat");
+        let mut other = Dictionary::new(2);
+        // "the" and "cat" get other indices than in `dict`
+        other.learn_from_line("a dog and the cat sat");
+        other.learn_from_line("the cat ran");
+        dict.merge(&other).unwrap();
+        let entry = vec![
+            dict.find_word("the").unwrap(),
+            dict.find_word("cat").unwrap(),
+        ];
+        let mut successors: Vec<(String, u32)> = dict
+            .successors(&entry)
+            .unwrap()
+            .into_iter()
+            .map(|(word, count)| (dict.word_str(word).to_owned(), count))
+            .collect();
+        successors.sort();
+        assert_eq!(
+            successors,
+            vec![("ran".to_owned(), 1), ("sat".to_owned(), 2)]
+        );
+        assert!(dict.find_word("dog").is_some());
+
+        match Dictionary::new(3).merge(&dict) {
+            Err(DictionaryError::OrderMismatch {
+                expected: 3,
+                actual: 2,
+            }) => {}
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(()) => panic!("merged dictionaries of different orders"),
+        }
+    }
+
+    fn entry(dict: &Dictionary, words: &[&str]) -> Vec<Word> {
+        words
+            .iter()
+            .map(|word| dict.find_word(word).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn removed_entries_are_not_led_to() {
+        let mut dict = Dictionary::new(2);
+        for line in &["the cat sat", "the dog sat", "a cat sat"] {
+            dict.learn_from_line(line);
+        }
+        let the_cat = entry(&dict, &["the", "cat"]);
+        assert!(dict.remove_entry(&the_cat));
+        assert!(!dict.remove_entry(&the_cat));
+        // "the cat sat" can't be generated anymore, "a cat sat" still can
+        assert_eq!(
+            transitions(&dict),
+            vec![
+                "[\"<start1>\", \"<start2>\"] a 1",
+                "[\"<start1>\", \"<start2>\"] the 2",
+                "[\"<start2>\", \"a\"] cat 1",
+                "[\"<start2>\", \"the\"] dog 1",
+                "[\"a\", \"cat\"] sat 1",
+                "[\"cat\", \"sat\"] <end> 2",
+                "[\"dog\", \"sat\"] <end> 1",
+                "[\"the\", \"dog\"] sat 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn removed_entries_are_not_led_to_at_order_1() {
+        let mut dict = Dictionary::new(1);
+        for line in &["a b", "a c", "b c"] {
+            dict.learn_from_line(line);
+        }
+        let b = entry(&dict, &["b"]);
+        assert!(dict.remove_entry(&b));
+        // with a single word in the entries, every one of them can lead to "b"
+        assert_eq!(
+            transitions(&dict),
+            vec!["[\"<start2>\"] a 2", "[\"a\"] c 1", "[\"c\"] <end> 2"]
+        );
+    }
+
+    #[test]
+    fn compacting_renumbers_the_words() {
+        let mut dict = Dictionary::new(2);
+        for line in &["a dog ran", "the cat sat", "the cat sat"] {
+            dict.learn_from_line(line);
+        }
+        assert_eq!(dict.prune(2), 4);
+        let pruned = transitions(&dict);
+        assert_eq!(dict.num_words(), 6);
+        assert_eq!(dict.compact(), 3);
+        assert_eq!(dict.num_words(), 3);
+        let words: Vec<&str> = (0..3)
+            .map(|index| dict.word_str(Word::Word(index)))
+            .collect();
+        assert_eq!(words, vec!["the", "cat", "sat"]);
+        assert!(dict.find_word("dog").is_none());
+        assert_eq!(transitions(&dict), pruned);
+        assert_eq!(transitions(&dict).len(), 4);
+    }
+
+    #[test]
+    fn decay_carries_the_leftover_time() {
+        let path = temp_path("decay");
+        let mut dict = Dictionary::new(2);
+        for _ in 0..8 {
+            dict.learn_from_line("the cat sat");
+        }
+        dict.learn_from_line("a dog");
+        assert_eq!(dict.decay(1000, 100), 0);
+        dict.save(&path).unwrap();
+        let mut dict = Dictionary::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(dict.decay(1250, 100), 2);
+        assert_eq!(dict.decayed_at(), 1200);
+        assert_eq!(
+            transitions(&dict),
+            vec![
+                "[\"<start1>\", \"<start2>\"] the 2",
+                "[\"<start2>\", \"the\"] cat 2",
+                "[\"cat\", \"sat\"] <end> 2",
+                "[\"the\", \"cat\"] sat 2",
+            ]
+        );
+        assert!(dict.find_word("dog").is_none());
+        assert_eq!(dict.decay(1299, 100), 0);
+        // the 50 seconds left over from the last time count
+        assert_eq!(dict.decay(1300, 100), 1);
+        assert_eq!(transitions(&dict).len(), 4);
+        assert_eq!(dict.decay(1450, 100), 1);
+        assert_eq!(dict.decayed_at(), 1400);
+        assert!(transitions(&dict).is_empty());
+        assert_eq!(dict.num_words(), 0);
+        // the lines are still known, see `decay`
+        assert_eq!(dict.line_hashes().len(), 2);
+    }
+
+    fn remove_with_backups(path: &Path) {
+        let _ = fs::remove_file(path);
+        for suffix in &["tmp", "1", "2", "3", "4"] {
+            let _ = fs::remove_file(with_suffix(path, suffix));
+            let _ = fs::remove_dir(with_suffix(path, suffix));
+        }
+    }
+
+    /// Saves four versions of a dictionary, returns their contents
+    fn save_versions(path: &Path, backups: usize) -> Vec<Vec<u8>> {
+        let mut dict = Dictionary::new(2);
+        let mut versions = Vec::new();
+        for line in &["the cat sat", "the dog sat", "a bird sang", "the end"] {
+            dict.learn_from_line(line);
+            dict.save_with_backups(path, backups).unwrap();
+            versions.push(fs::read(path).unwrap());
+        }
+        assert!(!with_suffix(path, "tmp").exists());
+        versions
+    }
+
+    #[test]
+    fn backups_are_rotated() {
+        let path = temp_path("backups3");
+        remove_with_backups(&path);
+        let versions = save_versions(&path, 3);
+        for (i, version) in versions.iter().rev().skip(1).enumerate() {
+            assert_eq!(
+                &fs::read(with_suffix(&path, (i + 1).to_string())).unwrap(),
+                version
+            );
+        }
+        assert!(!with_suffix(&path, "4").exists());
+        remove_with_backups(&path);
+
+        let path = temp_path("backups1");
+        remove_with_backups(&path);
+        let versions = save_versions(&path, 1);
+        assert_eq!(fs::read(with_suffix(&path, "1")).unwrap(), versions[2]);
+        assert!(!with_suffix(&path, "2").exists());
+        remove_with_backups(&path);
+    }
+
+    #[test]
+    fn first_save_makes_no_backup() {
+        let path = temp_path("first-save");
+        remove_with_backups(&path);
+        let mut dict = learned();
+        dict.save_with_backups(&path, 3).unwrap();
+        assert!(path.exists());
+        assert!(!with_suffix(&path, "1").exists());
+        assert!(!with_suffix(&path, "tmp").exists());
+        assert_eq!(
+            transitions(&Dictionary::load(&path).unwrap()),
+            transitions(&learned())
+        );
+        remove_with_backups(&path);
+    }
+
+    #[test]
+    fn failed_rotation_leaves_no_temporary_file() {
+        let path = temp_path("failed-rotation");
+        remove_with_backups(&path);
+        let mut dict = learned();
+        dict.save_with_backups(&path, 1).unwrap();
+        let saved = fs::read(&path).unwrap();
+        // a directory in place of the backup can't be removed as a file
+        fs::create_dir(with_suffix(&path, "1")).unwrap();
+        dict.learn_from_line("the cat ran away");
+        assert!(dict.save_with_backups(&path, 1).is_err());
+        assert!(!with_suffix(&path, "tmp").exists());
+        assert_eq!(fs::read(&path).unwrap(), saved);
+        remove_with_backups(&path);
     }
 }
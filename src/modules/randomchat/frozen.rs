@@ -0,0 +1,625 @@
+//! The dictionary file format, laid out so that it can be used directly from a memory-mapped
+//! file, without reading it into memory.
+//!
+//! All the numbers are little-endian u32s. The file consists of:
+//! - the header: magic, format version, order, number of words, length of the word arena, the
+//!   numbers of entries and transitions of the forward and the reverse tables, the number of
+//!   learned lines (since version 4) and the time of the last decay in seconds since the Unix
+//!   epoch, as a u64 (since version 5)
+//! - the offsets of the words in the arena, followed by the end of the last word
+//! - the indices of the words, sorted by their lowercased text
+//! - the arena: the text of all the words back to back, padded to a multiple of 4 bytes
+//! - the forward table: its entries, sorted, each followed by the index of its first
+//!   transition, and then the transitions of all the entries: the next word and the cumulative
+//!   count of the entry's transitions up to and including this one
+//! - the reverse table, in the same layout
+//! - the hashes of the learned lines, sorted, as u64s (since version 4)
+//! - CRC32 of everything before it
+
+use super::dictionary::{
+    to_4u8, to_u32, Crc32, DictionaryError, Key, FIRST_SENTINEL, FORMAT_VERSION, MAGIC, MAX_ORDER,
+};
+use super::words::cmp_ignore_case;
+use memmap::Mmap;
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::ops::{Deref, Range};
+use std::path::Path;
+use std::str;
+
+/// The first version in the format described above
+const FIRST_VERSION: u32 = 3;
+
+/// The contents of a dictionary file
+pub enum Data {
+    Mapped(Mmap),
+    Read(Vec<u8>),
+}
+
+impl Data {
+    /// Maps the file into memory, or reads it if it can't be mapped
+    pub fn open(path: &Path) -> io::Result<Data> {
+        let mut file = File::open(path)?;
+        // empty files can't be mapped
+        if file.metadata()?.len() > 0 {
+            // the dictionaries are only ever replaced by renaming new files over them, never
+            // modified in place, so the mapped contents don't change
+            if let Ok(map) = unsafe { Mmap::map(&file) } {
+                return Ok(Data::Mapped(map));
+            }
+        }
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        Ok(Data::Read(bytes))
+    }
+}
+
+impl Deref for Data {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match *self {
+            Data::Mapped(ref map) => &map[..],
+            Data::Read(ref bytes) => &bytes[..],
+        }
+    }
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> u32 {
+    to_u32(&bytes[pos..pos + 4]).unwrap()
+}
+
+fn read_u64(bytes: &[u8], pos: usize) -> u64 {
+    read_u32(bytes, pos) as u64 + ((read_u32(bytes, pos + 4) as u64) << 32)
+}
+
+/// magic, version, order, words, arena length, entries and transitions of both tables, lines,
+/// time of the last decay
+fn header_len(version: u32) -> usize {
+    if version >= 5 {
+        4 * 12
+    } else if version >= 4 {
+        4 * 10
+    } else {
+        4 * 9
+    }
+}
+
+/// Whether the data is in the format described above, rather than one of the older ones
+pub fn is_frozen(data: &[u8]) -> bool {
+    if data.len() < 8 || !data.starts_with(MAGIC) {
+        return false;
+    }
+    let version = read_u32(data, 4);
+    version >= FIRST_VERSION && version <= FORMAT_VERSION
+}
+
+/// The index of the first element of `0..len` for which `less` is false, given that it is true
+/// for all the elements before it
+fn lower_bound<F: Fn(usize) -> bool>(len: usize, less: F) -> usize {
+    let (mut low, mut high) = (0, len);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if less(mid) {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    low
+}
+
+/// The transitions of a frozen dictionary, in one direction
+#[derive(Clone, Copy)]
+pub struct Table<'a> {
+    order: usize,
+    entries: &'a [u8],
+    transitions: &'a [u8],
+}
+
+impl<'a> Table<'a> {
+    fn entry_size(&self) -> usize {
+        (self.order + 1) * 4
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len() / self.entry_size()
+    }
+
+    fn num_transitions(&self) -> usize {
+        self.transitions.len() / 8
+    }
+
+    pub fn key(&self, index: usize) -> Key {
+        let pos = index * self.entry_size();
+        let mut key = [0; MAX_ORDER];
+        for (i, word) in key.iter_mut().take(self.order).enumerate() {
+            *word = read_u32(self.entries, pos + i * 4);
+        }
+        key
+    }
+
+    fn first_transition(&self, index: usize) -> usize {
+        if index == self.len() {
+            return self.num_transitions();
+        }
+        read_u32(self.entries, index * self.entry_size() + self.order * 4) as usize
+    }
+
+    /// The indices of the entry's transitions
+    fn transitions_of(&self, index: usize) -> Range<usize> {
+        self.first_transition(index)..self.first_transition(index + 1)
+    }
+
+    fn word(&self, transition: usize) -> u32 {
+        read_u32(self.transitions, transition * 8)
+    }
+
+    fn cumulative(&self, transition: usize) -> u32 {
+        read_u32(self.transitions, transition * 8 + 4)
+    }
+
+    /// The index of the first entry not less than `key`
+    fn lower_bound(&self, key: &Key) -> usize {
+        lower_bound(self.len(), |index| self.key(index) < *key)
+    }
+
+    pub fn find(&self, key: &Key) -> Option<usize> {
+        let index = self.lower_bound(key);
+        if index < self.len() && self.key(index) == *key {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    /// The indices of the entries starting with `word`
+    pub fn starting_with(&self, word: u32) -> Range<usize> {
+        let mut key = [0; MAX_ORDER];
+        key[0] = word;
+        let start = self.lower_bound(&key);
+        let end = match word.checked_add(1) {
+            Some(next) => {
+                key[0] = next;
+                self.lower_bound(&key)
+            }
+            None => self.len(),
+        };
+        start..end
+    }
+
+    /// Replaces the contents of `out` with the words following the entry and their counts
+    pub fn successors(&self, index: usize, out: &mut Vec<(u32, u32)>) {
+        out.clear();
+        let mut previous = 0;
+        for transition in self.transitions_of(index) {
+            let cumulative = self.cumulative(transition);
+            out.push((self.word(transition), cumulative - previous));
+            previous = cumulative;
+        }
+    }
+
+    /// The sum of the counts of the entry's transitions
+    pub fn total(&self, index: usize) -> u32 {
+        let range = self.transitions_of(index);
+        if range.start == range.end {
+            0
+        } else {
+            self.cumulative(range.end - 1)
+        }
+    }
+
+    /// The word at position `value` when the words following the entry are laid out one after
+    /// another, each taking as many positions as its count. `value` must be less than the total.
+    pub fn sample(&self, index: usize, value: u32) -> u32 {
+        let range = self.transitions_of(index);
+        let offset = lower_bound(range.end - range.start, |i| {
+            self.cumulative(range.start + i) <= value
+        });
+        self.word(range.start + offset)
+    }
+}
+
+/// A dictionary read from a file in the format described above
+pub struct Frozen {
+    data: Data,
+    order: usize,
+    num_words: usize,
+    offsets: usize,
+    sorted: usize,
+    arena: usize,
+    forward: (Range<usize>, Range<usize>),
+    reverse: (Range<usize>, Range<usize>),
+    lines: Range<usize>,
+    decayed_at: u64,
+}
+
+impl Frozen {
+    /// Checks the layout, and that the contents can't make the accessors panic. The contents
+    /// aren't copied, and the checksum is only checked by `verify`.
+    pub fn new(data: Data) -> Result<Frozen, DictionaryError> {
+        let frozen = Frozen::written(data)?;
+        frozen.validate()?;
+        Ok(frozen)
+    }
+
+    /// Like `new`, but only checks the header and that the file is as long as it says, which
+    /// takes no time regardless of the size. For files just written by `write`.
+    pub fn written(data: Data) -> Result<Frozen, DictionaryError> {
+        if data.len() < 8 {
+            return Err(DictionaryError::Truncated);
+        }
+        let version = read_u32(&data, 4);
+        let header_len = header_len(version);
+        if data.len() < header_len + 4 {
+            return Err(DictionaryError::Truncated);
+        }
+        let mut header: Vec<u64> = (0..header_len / 4)
+            .map(|i| read_u32(&data, i * 4) as u64)
+            .collect();
+        // no lines before version 4, never decayed before version 5
+        header.resize(12, 0);
+        let order = header[2];
+        if order < 1 || order > MAX_ORDER as u64 {
+            return Err(DictionaryError::InvalidOrder(order as u32));
+        }
+        let num_words = header[3];
+        let arena_len = header[4];
+        let entry_size = (order + 1) * 4;
+        let lengths = [
+            (num_words + 1) * 4,
+            num_words * 4,
+            (arena_len + 3) / 4 * 4,
+            header[5] * entry_size,
+            header[6] * 8,
+            header[7] * entry_size,
+            header[8] * 8,
+            header[9] * 8,
+        ];
+        let mut starts = [header_len as u64; 9];
+        for (i, length) in lengths.iter().enumerate() {
+            starts[i + 1] = starts[i] + length;
+        }
+        let expected_len = starts[8] + 4;
+        if (data.len() as u64) < expected_len {
+            return Err(DictionaryError::Truncated);
+        }
+        if data.len() as u64 > expected_len {
+            return Err(DictionaryError::Corrupted(
+                "data after the end of the dictionary",
+            ));
+        }
+        let starts: Vec<usize> = starts.iter().map(|&start| start as usize).collect();
+        let frozen = Frozen {
+            order: order as usize,
+            num_words: num_words as usize,
+            offsets: starts[0],
+            sorted: starts[1],
+            arena: starts[2],
+            forward: (starts[3]..starts[4], starts[4]..starts[5]),
+            reverse: (starts[5]..starts[6], starts[6]..starts[7]),
+            lines: starts[7]..starts[8],
+            decayed_at: header[10] + (header[11] << 32),
+            data,
+        };
+        Ok(frozen)
+    }
+
+    /// Checks the checksum, which catches damage that `new` lets through because it can't
+    /// cause a panic, e.g. changed words or counts
+    pub fn verify(&self) -> Result<(), DictionaryError> {
+        let (contents, trailer) = self.data.split_at(self.data.len() - 4);
+        let expected = read_u32(trailer, 0);
+        let actual = {
+            let mut crc = Crc32::new();
+            crc.update(contents);
+            crc.finish()
+        };
+        if expected != actual {
+            return Err(DictionaryError::ChecksumMismatch { expected, actual });
+        }
+        Ok(())
+    }
+
+    /// Makes sure that the accessors won't panic, and that the tables can be binary searched
+    fn validate(&self) -> Result<(), DictionaryError> {
+        let arena_len = read_u32(&self.data, 16) as usize;
+        let mut previous = 0;
+        for index in 0..self.num_words + 1 {
+            let offset = read_u32(&self.data, self.offsets + index * 4) as usize;
+            if offset < previous || offset > arena_len || (index == 0 && offset != 0) {
+                return Err(DictionaryError::Corrupted("invalid word offsets"));
+            }
+            if index > 0 {
+                let start = self.arena + previous;
+                str::from_utf8(&self.data[start..self.arena + offset])
+                    .map_err(|_| DictionaryError::InvalidUtf8)?;
+            }
+            previous = offset;
+        }
+        if previous != arena_len {
+            return Err(DictionaryError::Corrupted("invalid word offsets"));
+        }
+        for index in self.sorted_words() {
+            self.check_word(index)?;
+        }
+        for &reverse in &[false, true] {
+            let table = self.table(reverse);
+            let mut previous_first = 0;
+            for index in 0..table.len() {
+                let key = table.key(index);
+                for &word in &key[..self.order] {
+                    self.check_word(word)?;
+                }
+                if index > 0 && table.key(index - 1) >= key {
+                    return Err(DictionaryError::Corrupted("entries out of order"));
+                }
+                let first = table.first_transition(index);
+                if first < previous_first || first > table.num_transitions() {
+                    return Err(DictionaryError::Corrupted("invalid transition indices"));
+                }
+                previous_first = first;
+            }
+            for index in 0..table.len() {
+                let mut previous_cumulative = 0;
+                for transition in table.transitions_of(index) {
+                    self.check_word(table.word(transition))?;
+                    let cumulative = table.cumulative(transition);
+                    if cumulative <= previous_cumulative {
+                        return Err(DictionaryError::Corrupted("invalid transition counts"));
+                    }
+                    previous_cumulative = cumulative;
+                }
+            }
+        }
+        for index in 1..self.num_lines() {
+            if self.line_hash(index - 1) >= self.line_hash(index) {
+                return Err(DictionaryError::Corrupted("line hashes out of order"));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_word(&self, word: u32) -> Result<(), DictionaryError> {
+        if (word as usize) < self.num_words || word >= FIRST_SENTINEL {
+            Ok(())
+        } else {
+            Err(DictionaryError::InvalidWordIndex(word))
+        }
+    }
+
+    pub fn order(&self) -> usize {
+        self.order
+    }
+
+    pub fn num_words(&self) -> usize {
+        self.num_words
+    }
+
+    pub fn word(&self, index: u32) -> &str {
+        let pos = self.offsets + index as usize * 4;
+        let start = self.arena + read_u32(&self.data, pos) as usize;
+        let end = self.arena + read_u32(&self.data, pos + 4) as usize;
+        str::from_utf8(&self.data[start..end]).unwrap()
+    }
+
+    fn sorted_word(&self, position: usize) -> u32 {
+        read_u32(&self.data, self.sorted + position * 4)
+    }
+
+    /// The indices of all the words, sorted by their lowercased text
+    pub fn sorted_words(&self) -> Vec<u32> {
+        (0..self.num_words)
+            .map(|position| self.sorted_word(position))
+            .collect()
+    }
+
+    /// Looks up a word, case-insensitively
+    pub fn find_word(&self, word: &str) -> Option<u32> {
+        let position = lower_bound(self.num_words, |position| {
+            cmp_ignore_case(self.word(self.sorted_word(position)), word) == Ordering::Less
+        });
+        if position == self.num_words {
+            return None;
+        }
+        let index = self.sorted_word(position);
+        if cmp_ignore_case(self.word(index), word) == Ordering::Equal {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    fn num_lines(&self) -> usize {
+        (self.lines.end - self.lines.start) / 8
+    }
+
+    fn line_hash(&self, index: usize) -> u64 {
+        read_u64(&self.data, self.lines.start + index * 8)
+    }
+
+    pub fn line_hashes(&self) -> Vec<u64> {
+        (0..self.num_lines())
+            .map(|index| self.line_hash(index))
+            .collect()
+    }
+
+    /// Whether a line with the given hash has been learned
+    pub fn contains_line(&self, hash: u64) -> bool {
+        let index = lower_bound(self.num_lines(), |index| self.line_hash(index) < hash);
+        index < self.num_lines() && self.line_hash(index) == hash
+    }
+
+    /// The time of the last decay, 0 if the dictionary has never been decayed
+    pub fn decayed_at(&self) -> u64 {
+        self.decayed_at
+    }
+
+    pub fn table(&self, reverse: bool) -> Table {
+        let (ref entries, ref transitions) = if reverse {
+            &self.reverse
+        } else {
+            &self.forward
+        };
+        Table {
+            order: self.order,
+            entries: &self.data[entries.clone()],
+            transitions: &self.data[transitions.clone()],
+        }
+    }
+}
+
+/// What `write` needs to know about a dictionary
+pub trait Source {
+    fn order(&self) -> usize;
+    fn num_words(&self) -> usize;
+    fn word(&self, index: u32) -> &str;
+    /// The indices of all the words, sorted by their lowercased text, the lower indices first
+    /// among the words differing only in case
+    fn sorted_words(&self) -> Vec<u32>;
+    /// Calls `f` with every entry of the forward or the reverse table, in order, and the words
+    /// following it with their counts, sorted by the word
+    fn for_each_entry(&self, reverse: bool, f: &mut FnMut(&Key, &[(u32, u32)]));
+    /// The hashes of the learned lines, sorted, without duplicates
+    fn line_hashes(&self) -> Vec<u64>;
+    /// The time of the last decay, in seconds since the Unix epoch
+    fn decayed_at(&self) -> u64;
+}
+
+/// Computes the checksum of everything written through it
+struct CrcWriter<W> {
+    inner: W,
+    crc: Crc32,
+}
+
+impl<W: Write> Write for CrcWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.crc.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "the dictionary is too large")
+}
+
+/// The number of entries and of transitions in the table
+fn table_size(source: &Source, reverse: bool) -> (usize, usize) {
+    let (mut entries, mut transitions) = (0, 0);
+    source.for_each_entry(reverse, &mut |_, successors| {
+        entries += 1;
+        transitions += successors.len();
+    });
+    (entries, transitions)
+}
+
+/// Writes the dictionary in the format described above
+pub fn write<W: Write>(source: &Source, out: W) -> io::Result<()> {
+    let num_words = source.num_words();
+    let arena_len: usize = (0..num_words)
+        .map(|index| source.word(index as u32).len())
+        .sum();
+    let (entries, transitions) = table_size(source, false);
+    let (rev_entries, rev_transitions) = table_size(source, true);
+    let lines = source.line_hashes();
+    let header = [
+        FORMAT_VERSION as usize,
+        source.order(),
+        num_words,
+        arena_len,
+        entries,
+        transitions,
+        rev_entries,
+        rev_transitions,
+        lines.len(),
+    ];
+    let decayed_at = source.decayed_at();
+    if header
+        .iter()
+        .any(|&value| value > u32::max_value() as usize)
+    {
+        return Err(too_large());
+    }
+
+    let mut out = CrcWriter {
+        inner: BufWriter::new(out),
+        crc: Crc32::new(),
+    };
+    out.write_all(MAGIC)?;
+    for &value in &header {
+        out.write_all(&to_4u8(value as u32))?;
+    }
+    out.write_all(&to_4u8(decayed_at as u32))?;
+    out.write_all(&to_4u8((decayed_at >> 32) as u32))?;
+    let mut offset = 0;
+    out.write_all(&to_4u8(0))?;
+    for index in 0..num_words {
+        offset += source.word(index as u32).len();
+        out.write_all(&to_4u8(offset as u32))?;
+    }
+    for index in source.sorted_words() {
+        out.write_all(&to_4u8(index))?;
+    }
+    for index in 0..num_words {
+        out.write_all(source.word(index as u32).as_bytes())?;
+    }
+    // keep the tables aligned
+    out.write_all(&[0; 3][..(4 - arena_len % 4) % 4])?;
+    write_table(source, false, &mut out)?;
+    write_table(source, true, &mut out)?;
+    for hash in lines {
+        out.write_all(&to_4u8(hash as u32))?;
+        out.write_all(&to_4u8((hash >> 32) as u32))?;
+    }
+
+    let crc = out.crc.finish();
+    let mut out = out.inner;
+    out.write_all(&to_4u8(crc))?;
+    out.flush()
+}
+
+fn write_table<W: Write>(source: &Source, reverse: bool, out: &mut W) -> io::Result<()> {
+    let order = source.order();
+    // `for_each_entry` can't stop early, so the first error is kept and the rest is skipped
+    let mut result = Ok(());
+    let mut record = Vec::new();
+    let mut first = 0;
+    source.for_each_entry(reverse, &mut |key, successors| {
+        if result.is_ok() {
+            record.clear();
+            for &word in &key[..order] {
+                record.extend_from_slice(&to_4u8(word));
+            }
+            record.extend_from_slice(&to_4u8(first));
+            result = out.write_all(&record);
+        }
+        first += successors.len() as u32;
+    });
+    result?;
+    let mut result = Ok(());
+    source.for_each_entry(reverse, &mut |_, successors| {
+        if result.is_ok() {
+            record.clear();
+            let mut cumulative: u32 = 0;
+            for &(word, count) in successors {
+                cumulative = match cumulative.checked_add(count) {
+                    Some(cumulative) => cumulative,
+                    None => {
+                        result = Err(too_large());
+                        return;
+                    }
+                };
+                record.extend_from_slice(&to_4u8(word));
+                record.extend_from_slice(&to_4u8(cumulative));
+            }
+            result = out.write_all(&record);
+        }
+    });
+    result
+}
@@ -0,0 +1,111 @@
+use modules::Endpoint;
+use regex::Regex;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use universal_chat::{Channel, SourceId};
+
+/// Whose messages RandomChat doesn't learn from: other bots and relays listed in the config,
+/// and the users who opted out, whose nicks are kept in a file
+pub struct Ignored {
+    /// lowercased
+    nicks: HashSet<String>,
+    /// matched against the text of the messages
+    patterns: Vec<Regex>,
+    channels: Vec<Endpoint>,
+    /// lowercased nicks of the users who opted out, regardless of the source
+    opted_out: HashSet<String>,
+    optout_path: String,
+}
+
+impl Ignored {
+    /// Reads the opt-outs from `optout_path`, if it exists. Panics on invalid patterns.
+    pub fn new(
+        nicks: Vec<String>,
+        patterns: Vec<String>,
+        channels: Vec<Endpoint>,
+        optout_path: String,
+    ) -> io::Result<Ignored> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern)
+                    .unwrap_or_else(|err| panic!("Invalid regex {}: {}", pattern, err))
+            })
+            .collect();
+        let opted_out = match fs::File::open(&optout_path) {
+            Ok(file) => {
+                let mut opted_out = HashSet::new();
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    if !line.trim().is_empty() {
+                        opted_out.insert(line.trim().to_lowercase());
+                    }
+                }
+                opted_out
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(Ignored {
+            nicks: nicks.iter().map(|nick| nick.to_lowercase()).collect(),
+            patterns,
+            channels,
+            opted_out,
+            optout_path,
+        })
+    }
+
+    /// Whether the message shouldn't be learned from
+    pub fn ignores(&self, source: &SourceId, channel: &Channel, author: &str, text: &str) -> bool {
+        let author = author.to_lowercase();
+        self.nicks.contains(&author)
+            || self.opted_out.contains(&author)
+            || self.patterns.iter().any(|pattern| pattern.is_match(text))
+            || self.channels.iter().any(|endpoint| {
+                endpoint.source == source.0
+                    && *channel == Channel::Channel(endpoint.channel.clone())
+            })
+    }
+
+    /// Whether the nick is on the configured list, as opposed to having opted out
+    pub fn ignores_nick(&self, nick: &str) -> bool {
+        self.nicks.contains(&nick.to_lowercase())
+    }
+
+    pub fn is_opted_out(&self, nick: &str) -> bool {
+        self.opted_out.contains(&nick.to_lowercase())
+    }
+
+    /// Stops or resumes learning from `nick` and saves the opt-outs. Nothing is changed if
+    /// saving fails.
+    pub fn set_opted_out(&mut self, nick: &str, opted_out: bool) -> io::Result<()> {
+        let nick = nick.to_lowercase();
+        if self.opted_out.contains(&nick) == opted_out {
+            return Ok(());
+        }
+        let mut new = self.opted_out.clone();
+        if opted_out {
+            new.insert(nick);
+        } else {
+            new.remove(&nick);
+        }
+        let mut nicks: Vec<&String> = new.iter().collect();
+        nicks.sort();
+        let mut contents = String::new();
+        for nick in nicks {
+            contents.push_str(nick);
+            contents.push('\n');
+        }
+        // replace the file at once, so that a failed write doesn't lose the other opt-outs
+        let tmp_path = format!("{}.tmp", self.optout_path);
+        fs::write(&tmp_path, contents)
+            .and_then(|_| fs::rename(&tmp_path, &self.optout_path))
+            .map_err(|err| {
+                let _ = fs::remove_file(&tmp_path);
+                err
+            })?;
+        self.opted_out = new;
+        Ok(())
+    }
+}
@@ -0,0 +1,375 @@
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveTime, Timelike, Utc};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+/// The hours during which RandomChat doesn't speak unprompted, as given in the config
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QuietHoursConfig {
+    /// "HH:MM"
+    pub from: String,
+    pub to: String,
+    /// "+HH:MM" or "-HH:MM", the local time zone of the machine if not given
+    pub timezone: Option<String>,
+}
+
+struct QuietHours {
+    from: NaiveTime,
+    to: NaiveTime,
+    /// `None` for the local time zone
+    offset: Option<FixedOffset>,
+}
+
+fn parse_offset(offset: &str) -> Option<FixedOffset> {
+    let sign = match offset.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => return None,
+    };
+    let time = NaiveTime::parse_from_str(&offset[1..], "%H:%M").ok()?;
+    FixedOffset::east_opt(sign * time.num_seconds_from_midnight() as i32)
+}
+
+impl QuietHours {
+    /// Panics if the config is invalid
+    fn new(config: &QuietHoursConfig) -> QuietHours {
+        let parse_time = |time: &str| {
+            NaiveTime::parse_from_str(time, "%H:%M")
+                .unwrap_or_else(|_| panic!("Invalid RandomChat quiet hours time: {}", time))
+        };
+        QuietHours {
+            from: parse_time(&config.from),
+            to: parse_time(&config.to),
+            offset: config.timezone.as_ref().map(|timezone| {
+                parse_offset(timezone).unwrap_or_else(|| {
+                    panic!("Invalid RandomChat quiet hours timezone: {}", timezone)
+                })
+            }),
+        }
+    }
+
+    fn contains(&self, now: DateTime<Utc>) -> bool {
+        let time = match self.offset {
+            Some(offset) => now.with_timezone(&offset).time(),
+            None => now.with_timezone(&Local).time(),
+        };
+        if self.from <= self.to {
+            time >= self.from && time < self.to
+        } else {
+            // over midnight
+            time >= self.from || time < self.to
+        }
+    }
+}
+
+impl fmt::Display for QuietHours {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.from.format("%H:%M"),
+            self.to.format("%H:%M")
+        )?;
+        match self.offset {
+            Some(offset) => write!(f, " UTC{}", offset),
+            None => write!(f, " local time"),
+        }
+    }
+}
+
+/// Why RandomChat can't speak in a channel right now
+#[derive(Clone, Copy, Debug)]
+pub enum Blocked {
+    QuietHours,
+    /// for how long
+    Cooldown(Duration),
+    RateLimit(Duration),
+    BotBackoff(Duration),
+}
+
+/// Formats the duration as e.g. "1h 5m 3s"
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.num_seconds().max(1);
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    let mut parts = Vec::new();
+    if hours > 0 {
+        parts.push(format!("{}h", hours));
+    }
+    if minutes > 0 {
+        parts.push(format!("{}m", minutes));
+    }
+    if seconds > 0 {
+        parts.push(format!("{}s", seconds));
+    }
+    parts.join(" ")
+}
+
+impl fmt::Display for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Blocked::QuietHours => write!(f, "quiet hours"),
+            Blocked::Cooldown(left) => write!(f, "cooldown, {} left", format_duration(left)),
+            Blocked::RateLimit(left) => {
+                write!(f, "too many messages, {} left", format_duration(left))
+            }
+            Blocked::BotBackoff(left) => {
+                write!(f, "backing off from bots, {} left", format_duration(left))
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct ChannelState {
+    /// when the messages within the rate window were sent, the oldest first
+    sent: VecDeque<DateTime<Utc>>,
+    /// the number of replies to bots since a human last spoke
+    bot_replies: u32,
+    last_bot_reply: Option<DateTime<Utc>>,
+}
+
+/// Limits on how often RandomChat speaks unprompted, tracked separately for each channel
+pub struct Limits {
+    cooldown: Duration,
+    /// 0 for no limit
+    max_messages: usize,
+    window: Duration,
+    bot_backoff: Duration,
+    quiet_hours: Option<QuietHours>,
+    channels: HashMap<String, ChannelState>,
+}
+
+impl Limits {
+    /// Panics if `quiet_hours` is invalid
+    pub fn new(
+        cooldown: Duration,
+        max_messages: usize,
+        window: Duration,
+        bot_backoff: Duration,
+        quiet_hours: Option<&QuietHoursConfig>,
+    ) -> Limits {
+        Limits {
+            cooldown,
+            max_messages,
+            window,
+            bot_backoff,
+            quiet_hours: quiet_hours.map(QuietHours::new),
+            channels: HashMap::new(),
+        }
+    }
+
+    /// Whether a message can be sent to the channel now. `to_bot` tells whether it would be a
+    /// reply to the bot itself or to another bot, which is allowed less and less often.
+    pub fn check(&self, channel: &str, to_bot: bool, now: DateTime<Utc>) -> Result<(), Blocked> {
+        if self
+            .quiet_hours
+            .as_ref()
+            .map_or(false, |quiet| quiet.contains(now))
+        {
+            return Err(Blocked::QuietHours);
+        }
+        let state = match self.channels.get(channel) {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+        if let Some(&last) = state.sent.back() {
+            let left = last + self.cooldown - now;
+            if left > Duration::zero() {
+                return Err(Blocked::Cooldown(left));
+            }
+        }
+        if self.max_messages > 0 && state.sent.len() >= self.max_messages {
+            let oldest = state.sent[state.sent.len() - self.max_messages];
+            let left = oldest + self.window - now;
+            if left > Duration::zero() {
+                return Err(Blocked::RateLimit(left));
+            }
+        }
+        if let (true, Some(last)) = (to_bot, state.last_bot_reply) {
+            // doubled with every reply, up to 1024 times
+            let backoff = self.bot_backoff * (1 << (state.bot_replies - 1).min(10)) as i32;
+            let left = last + backoff - now;
+            if left > Duration::zero() {
+                return Err(Blocked::BotBackoff(left));
+            }
+        }
+        Ok(())
+    }
+
+    /// Records a message sent to the channel
+    pub fn sent(&mut self, channel: &str, to_bot: bool, now: DateTime<Utc>) {
+        let (window, max_messages) = (self.window, self.max_messages);
+        let state = self
+            .channels
+            .entry(channel.to_owned())
+            .or_insert_with(ChannelState::default);
+        state.sent.push_back(now);
+        // the last one is always kept for the cooldown
+        while state.sent.len() > 1
+            && (state.sent.len() > max_messages
+                || state
+                    .sent
+                    .front()
+                    .map_or(false, |&time| time + window <= now))
+        {
+            state.sent.pop_front();
+        }
+        if to_bot {
+            state.bot_replies += 1;
+            state.last_bot_reply = Some(now);
+        }
+    }
+
+    /// Resets the backoff when a human writes in the channel
+    pub fn human_spoke(&mut self, channel: &str) {
+        if let Some(state) = self.channels.get_mut(channel) {
+            state.bot_replies = 0;
+            state.last_bot_reply = None;
+        }
+    }
+
+    /// A description of the limits and of the state of the channel
+    pub fn status(&self, channel: &str, now: DateTime<Utc>) -> String {
+        let mut parts = Vec::new();
+        if self.cooldown > Duration::zero() {
+            parts.push(format!("cooldown {}", format_duration(self.cooldown)));
+        }
+        if self.max_messages > 0 {
+            let sent = self.channels.get(channel).map_or(0, |state| {
+                state
+                    .sent
+                    .iter()
+                    .filter(|&&time| time + self.window > now)
+                    .count()
+            });
+            parts.push(format!(
+                "{}/{} messages per {}",
+                sent,
+                self.max_messages,
+                format_duration(self.window)
+            ));
+        }
+        if self.bot_backoff > Duration::zero() {
+            parts.push(format!("bot backoff {}", format_duration(self.bot_backoff)));
+        }
+        if let Some(ref quiet) = self.quiet_hours {
+            parts.push(format!("quiet hours {}", quiet));
+        }
+        if parts.is_empty() {
+            parts.push("no limits".to_owned());
+        }
+        let state = match self.check(channel, false, now) {
+            Ok(()) => "can speak now".to_owned(),
+            Err(blocked) => format!("silent: {}", blocked),
+        };
+        format!("{}; {}", parts.join(", "), state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The given "HH:MM:SS" on the same day
+    fn at(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(&format!("2020-01-01T{}Z", time))
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn limits(cooldown: i64, max_messages: usize, window: i64, bot_backoff: i64) -> Limits {
+        Limits::new(
+            Duration::seconds(cooldown),
+            max_messages,
+            Duration::seconds(window),
+            Duration::seconds(bot_backoff),
+            None,
+        )
+    }
+
+    #[test]
+    fn cooldown_is_per_channel() {
+        let mut limits = limits(30, 0, 3600, 0);
+        assert!(limits.check("#a", false, at("12:00:00")).is_ok());
+        limits.sent("#a", false, at("12:00:00"));
+        match limits.check("#a", false, at("12:00:20")) {
+            Err(Blocked::Cooldown(left)) => assert_eq!(left, Duration::seconds(10)),
+            other => panic!("{:?}", other),
+        }
+        assert!(limits.check("#b", false, at("12:00:20")).is_ok());
+        assert!(limits.check("#a", false, at("12:00:30")).is_ok());
+    }
+
+    #[test]
+    fn window_slides() {
+        let mut limits = limits(0, 2, 60, 0);
+        limits.sent("#a", false, at("12:00:00"));
+        limits.sent("#a", false, at("12:00:30"));
+        match limits.check("#a", false, at("12:00:59")) {
+            Err(Blocked::RateLimit(left)) => assert_eq!(left, Duration::seconds(1)),
+            other => panic!("{:?}", other),
+        }
+        // the first one is just out of the window
+        assert!(limits.check("#a", false, at("12:01:00")).is_ok());
+        limits.sent("#a", false, at("12:01:00"));
+        match limits.check("#a", false, at("12:01:10")) {
+            Err(Blocked::RateLimit(left)) => assert_eq!(left, Duration::seconds(20)),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn no_message_limit() {
+        let mut limits = limits(0, 0, 60, 0);
+        for second in 0..50 {
+            let now = at(&format!("12:00:{:02}", second));
+            assert!(limits.check("#a", false, now).is_ok());
+            limits.sent("#a", false, now);
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_until_a_human_speaks() {
+        let mut limits = limits(0, 0, 60, 10);
+        limits.sent("#a", true, at("12:00:00"));
+        assert!(limits.check("#a", true, at("12:00:10")).is_ok());
+        limits.sent("#a", true, at("12:00:10"));
+        match limits.check("#a", true, at("12:00:25")) {
+            Err(Blocked::BotBackoff(left)) => assert_eq!(left, Duration::seconds(5)),
+            other => panic!("{:?}", other),
+        }
+        // only replies to bots back off
+        assert!(limits.check("#a", false, at("12:00:25")).is_ok());
+        limits.human_spoke("#a");
+        assert!(limits.check("#a", true, at("12:00:11")).is_ok());
+        limits.sent("#a", true, at("12:00:11"));
+        assert!(limits.check("#a", true, at("12:00:21")).is_ok());
+    }
+
+    #[test]
+    fn quiet_hours_over_midnight() {
+        let config = QuietHoursConfig {
+            from: "23:00".to_owned(),
+            to: "07:00".to_owned(),
+            timezone: Some("+01:00".to_owned()),
+        };
+        let limits = Limits::new(
+            Duration::zero(),
+            0,
+            Duration::zero(),
+            Duration::zero(),
+            Some(&config),
+        );
+        let quiet = |time| match limits.check("#a", false, at(time)) {
+            Err(Blocked::QuietHours) => true,
+            Ok(()) => false,
+            Err(blocked) => panic!("{:?}", blocked),
+        };
+        // in UTC, an hour behind
+        assert!(!quiet("21:59:59"));
+        assert!(quiet("22:00:00"));
+        assert!(quiet("23:30:00"));
+        assert!(quiet("00:30:00"));
+        assert!(quiet("05:59:59"));
+        assert!(!quiet("06:00:00"));
+    }
+}
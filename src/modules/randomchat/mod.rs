@@ -1,4 +1,17 @@
+mod authors;
+mod dictionaries;
 mod dictionary;
+mod frozen;
+mod ignore;
+mod limits;
 mod randomchat;
+mod stats;
+mod successors;
+pub mod tokenizer;
+mod words;
 
+pub use self::dictionary::{
+    Dictionary, DictionaryError, Quality, Sampling, Word, DEFAULT_ORDER, MAX_ORDER,
+};
 pub use self::randomchat::RandomChat;
+pub use self::successors::Successors;
@@ -1,19 +1,49 @@
-use super::dictionary::Dictionary;
-use chrono::Duration;
+use super::authors::{Authors, AuthorsConfig};
+use super::dictionaries::{Dictionaries, DictionaryGroup, Split};
+use super::dictionary::{self, Dictionary, Quality, Sampling};
+use super::ignore::Ignored;
+use super::limits::{Limits, QuietHoursConfig};
+use super::stats;
+use super::tokenizer;
+use super::words::eq_ignore_case;
+use chrono::{Duration, Utc};
 use config::CONFIG;
-use modules::Command;
-use rand::{self, Rng};
+use modules::{
+    dispatch, module_rng, register, ArgSpec, Command, CommandSpec, Dispatch, Endpoint, ModuleRng,
+};
+use rand::Rng;
+use std::collections::HashSet;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
 use toml::Value;
 use universal_chat::{
-    CoreAPI, Event, Message, MessageContent, Module, ResumeEventHandling, SourceEvent, SourceId,
+    Channel, CoreAPI, Event, Message, MessageContent, Module, ResumeEventHandling, SourceEvent,
+    SourceId,
 };
 
 pub struct RandomChat {
     module_id: String,
-    dict: Dictionary,
-    dict_path: String,
+    dicts: Dictionaries,
+    backups: usize,
+    admins: Vec<Endpoint>,
+    /// allowed to make RandomChat forget things
+    moderators: Vec<Moderator>,
+    ignored: Ignored,
+    /// `None` unless imitating authors is enabled
+    authors: Option<Authors>,
+    limits: Limits,
+    // paths of the dictionaries whose last save failed, so that the admins are only notified
+    // about changes
+    failing_saves: HashSet<String>,
     enabled: bool,
     probability: u8,
+    context_replies: bool,
+    reply_when_addressed: bool,
+    quality: Quality,
+    sampling: Sampling,
+    /// in seconds
+    decay_half_life: Option<u64>,
+    rng: ModuleRng,
     timer_initialised: bool,
 }
 
@@ -22,6 +52,142 @@ impl RandomChat {
         core.schedule_timer(self.module_id.clone(), Duration::minutes(10));
         self.timer_initialised = true;
     }
+
+    /// Stores a setting changed at runtime in the module's config
+    fn store_config(&self, key: &str, value: Value) {
+        let mut config = CONFIG.lock().ok().expect("Couldn't lock CONFIG");
+        config
+            .modules
+            .get_mut(&self.module_id)
+            .unwrap_or_else(|| panic!("Couldn't find module {:?}", self.module_id))
+            .config
+            .as_mut()
+            .and_then(Value::as_table_mut)
+            .map(|config| config.insert(key.to_owned(), value));
+    }
+}
+
+fn reply(core: &mut CoreAPI, src: &SourceId, channel: Channel, text: String) {
+    core.send(
+        src,
+        Message {
+            author: "".to_owned(),
+            channel,
+            content: MessageContent::Text(text),
+        },
+    );
+}
+
+/// If the message is addressed to the bot - mentions `nick` or is a direct message - the rest of
+/// it, without the mentions. Discord's `<@id>` mentions count too, for sources that give the id
+/// as the nick.
+fn addressed_text(nick: &str, channel: &Channel, text: &str) -> Option<String> {
+    let mut mentioned = false;
+    let rest: Vec<&str> = text
+        .split_whitespace()
+        .filter(|word| {
+            let name = word.trim_end_matches(&[':', ',', '.', '!', '?', ';'][..]);
+            let name = if name.starts_with("<@") && name.ends_with('>') {
+                name[2..name.len() - 1].trim_start_matches('!')
+            } else {
+                name.trim_start_matches('@')
+            };
+            let mention = !nick.is_empty() && eq_ignore_case(name, nick);
+            mentioned |= mention;
+            !mention
+        })
+        .collect();
+    let direct = match *channel {
+        Channel::Channel(_) => false,
+        _ => true,
+    };
+    if mentioned || direct {
+        Some(rest.join(" "))
+    } else {
+        None
+    }
+}
+
+/// Identifies the channel in the limits
+fn channel_key(src: &SourceId, channel: &Channel) -> String {
+    match *channel {
+        Channel::Channel(ref name) => format!("{}/{}", src.0, name),
+        ref other => format!("{}/{:?}", src.0, other),
+    }
+}
+
+/// The commands of the module; `imitate` only exists with the per-author dictionaries enabled
+fn commands(imitate: bool) -> Vec<CommandSpec> {
+    let mut commands = vec![
+        CommandSpec::new(
+            "gadaj",
+            "Says a random sentence, built around the given words if any",
+        )
+        .arg(ArgSpec::rest("words").optional()),
+        CommandSpec::new("random", "Controls RandomChat")
+            .subcommand(CommandSpec::new("enable", "Starts learning and speaking"))
+            .subcommand(CommandSpec::new("disable", "Stops learning and speaking"))
+            .subcommand(
+                CommandSpec::new("forget", "Forgets a line everywhere, for moderators")
+                    .arg(ArgSpec::rest("text")),
+            )
+            .subcommand(
+                CommandSpec::new("forget-word", "Forgets a word everywhere, for moderators")
+                    .arg(ArgSpec::word("word")),
+            )
+            .subcommand(CommandSpec::new(
+                "optout",
+                "Stops learning from your messages",
+            ))
+            .subcommand(CommandSpec::new(
+                "optin",
+                "Resumes learning from your messages",
+            ))
+            .subcommand(CommandSpec::new(
+                "status",
+                "Shows the settings and whether RandomChat can speak now",
+            ))
+            .subcommand(CommandSpec::new(
+                "stats",
+                "Shows the size of the dictionary and its most common words",
+            ))
+            .subcommand(
+                CommandSpec::new(
+                    "about",
+                    "Shows the words most often said before and after a word",
+                )
+                .arg(ArgSpec::word("word")),
+            )
+            .subcommand(
+                CommandSpec::new("temperature", "Shows or sets the temperature")
+                    .arg(ArgSpec::word("value").optional()),
+            ),
+    ];
+    if imitate {
+        commands.push(
+            CommandSpec::new("imitate", "Says a random sentence in the style of someone")
+                .arg(ArgSpec::word("nick")),
+        );
+    }
+    commands
+}
+
+fn is_valid_temperature(temperature: f64) -> bool {
+    temperature.is_finite() && temperature >= 0.0
+}
+
+/// A user allowed to make RandomChat forget things, on a given source
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Moderator {
+    source: String,
+    nick: String,
+}
+
+impl Moderator {
+    /// Nicks are compared case-insensitively, like the ignored ones
+    fn is(&self, source: &SourceId, nick: &str) -> bool {
+        self.source == source.0 && self.nick.to_lowercase() == nick.to_lowercase()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -29,6 +195,37 @@ struct RandomChatConfig {
     enabled: bool,
     probability: u8,
     dictionary_path: Option<String>,
+    split: Option<Split>,
+    dictionaries: Option<Vec<DictionaryGroup>>,
+    order: Option<usize>,
+    tokenizer: Option<String>,
+    backups: Option<usize>,
+    admins: Option<Vec<Endpoint>>,
+    moderators: Option<Vec<Moderator>>,
+    ignore_nicks: Option<Vec<String>>,
+    ignore_patterns: Option<Vec<String>>,
+    ignore_channels: Option<Vec<Endpoint>>,
+    optout_path: Option<String>,
+    /// in seconds
+    cooldown: Option<u32>,
+    max_messages: Option<usize>,
+    /// in seconds
+    rate_window: Option<u32>,
+    /// in seconds
+    bot_backoff: Option<u32>,
+    quiet_hours: Option<QuietHoursConfig>,
+    context_replies: Option<bool>,
+    reply_when_addressed: Option<bool>,
+    min_words: Option<usize>,
+    max_words: Option<usize>,
+    retries: Option<usize>,
+    reject_copies: Option<bool>,
+    temperature: Option<f64>,
+    top_k: Option<usize>,
+    seed: Option<u64>,
+    /// in days
+    decay_half_life: Option<u64>,
+    authors: Option<AuthorsConfig>,
 }
 
 impl RandomChat {
@@ -41,15 +238,97 @@ impl RandomChat {
         let dict_path = config
             .dictionary_path
             .unwrap_or("dictionary.dat".to_owned());
-        let dict = Dictionary::load(&dict_path)
-            .ok()
-            .expect("Dictionary::load failed");
+        let order = config.order.unwrap_or(dictionary::DEFAULT_ORDER);
+        if order < 1 || order > dictionary::MAX_ORDER {
+            panic!(
+                "RandomChat order must be between 1 and {}",
+                dictionary::MAX_ORDER
+            );
+        }
+        let tokenizer = config.tokenizer.unwrap_or("chat".to_owned());
+        if tokenizer::from_name(&tokenizer).is_none() {
+            panic!("Unknown RandomChat tokenizer: {}", tokenizer);
+        }
+        let quality = Quality {
+            min_words: config.min_words.unwrap_or(2),
+            max_words: config.max_words.unwrap_or(30),
+            retries: config.retries.unwrap_or(10),
+            reject_copies: config.reject_copies.unwrap_or(true),
+        };
+        if quality.min_words > quality.max_words {
+            panic!("RandomChat min_words can't be greater than max_words");
+        }
+        let sampling = Sampling {
+            temperature: config.temperature.unwrap_or(1.0),
+            top_k: config.top_k.unwrap_or(0),
+        };
+        if !is_valid_temperature(sampling.temperature) {
+            panic!("RandomChat temperature must be a non-negative number");
+        }
+        if config.decay_half_life == Some(0) {
+            panic!("RandomChat decay_half_life must be positive");
+        }
+        let authors = config.authors.map(|authors| {
+            let order = authors.order.unwrap_or(1);
+            if order < 1 || order > dictionary::MAX_ORDER {
+                panic!(
+                    "RandomChat authors order must be between 1 and {}",
+                    dictionary::MAX_ORDER
+                );
+            }
+            Authors::new(
+                authors.path.clone(),
+                authors.max_authors.unwrap_or(100),
+                order,
+                tokenizer.clone(),
+            )
+            .unwrap_or_else(|err| panic!("Creating {} failed: {}", authors.path, err))
+        });
+        let mut dicts = Dictionaries::new(
+            dict_path,
+            config.split.unwrap_or(Split::None),
+            config.dictionaries.unwrap_or_default(),
+            order,
+            tokenizer,
+        );
+        // the other dictionaries are loaded when they are first needed. One that fails to load
+        // isn't used, and the error is reported to the admins like for the others.
+        let default_path = dicts.default_path().to_owned();
+        dicts.get(&default_path);
+        let optout_path = config.optout_path.unwrap_or("optout.txt".to_owned());
+        let ignored = Ignored::new(
+            config.ignore_nicks.unwrap_or_default(),
+            config.ignore_patterns.unwrap_or_default(),
+            config.ignore_channels.unwrap_or_default(),
+            optout_path.clone(),
+        )
+        .unwrap_or_else(|err| panic!("Reading {} failed: {}", optout_path, err));
+        let limits = Limits::new(
+            Duration::seconds(config.cooldown.unwrap_or(0) as i64),
+            config.max_messages.unwrap_or(0),
+            Duration::seconds(config.rate_window.unwrap_or(3600) as i64),
+            Duration::seconds(config.bot_backoff.unwrap_or(60) as i64),
+            config.quiet_hours.as_ref(),
+        );
+        register(&id, commands(authors.is_some()));
         Box::new(RandomChat {
             module_id: id,
-            dict,
-            dict_path,
+            dicts,
+            backups: config.backups.unwrap_or(3),
+            admins: config.admins.unwrap_or_default(),
+            moderators: config.moderators.unwrap_or_default(),
+            ignored,
+            authors,
+            limits,
+            failing_saves: HashSet::new(),
             enabled: config.enabled,
             probability: config.probability,
+            context_replies: config.context_replies.unwrap_or(false),
+            reply_when_addressed: config.reply_when_addressed.unwrap_or(false),
+            quality,
+            sampling,
+            decay_half_life: config.decay_half_life.map(|days| days * 24 * 60 * 60),
+            rng: module_rng(config.seed),
             timer_initialised: false,
         })
     }
@@ -59,10 +338,14 @@ impl Module for RandomChat {
     fn handle_event(&mut self, core: &mut CoreAPI, event: SourceEvent) -> ResumeEventHandling {
         let SourceEvent { source, event } = event;
         match event {
-            Event::ReceivedMessage(msg) => if let Some(cmd) = Command::from_msg(&msg) {
-                self.handle_command(core, source, cmd)
-            } else {
-                self.handle_message(core, source, msg)
+            Event::ReceivedMessage(msg) => match dispatch(&self.module_id, &source, &msg) {
+                Dispatch::NotCommand => self.handle_message(core, source, msg),
+                Dispatch::Run(cmd) => self.handle_command(core, source, cmd),
+                Dispatch::Reply(text) => {
+                    reply(core, &source, msg.channel, text);
+                    ResumeEventHandling::Stop
+                }
+                Dispatch::Other => ResumeEventHandling::Resume,
             },
             Event::Timer(id) => self.handle_timer(core, id),
             _ => ResumeEventHandling::Resume,
@@ -81,25 +364,79 @@ impl RandomChat {
             return ResumeEventHandling::Resume;
         }
         self.init_timer(core);
-        if core.get_nick(&src) != msg.author {
-            if let MessageContent::Text(txt) = msg.content {
-                self.dict.learn_from_line(txt);
+        let txt = match msg.content {
+            MessageContent::Text(txt) => Some(txt),
+            _ => None,
+        };
+        let path = self.dicts.path_for(&src, &msg.channel);
+        let nick = core.get_nick(&src);
+        // the rest of the message if it's addressed to the bot, which always replies then; other
+        // bots are never answered, so that they don't keep talking to each other
+        let answer = self.reply_when_addressed
+            && nick != msg.author
+            && !self.ignored.ignores_nick(&msg.author);
+        let addressed = match txt {
+            Some(ref txt) if answer => addressed_text(&nick, &msg.channel, txt),
+            _ => None,
+        };
+        let channel = channel_key(&src, &msg.channel);
+        let from_bot = nick == msg.author || self.ignored.ignores_nick(&msg.author);
+        if !from_bot {
+            self.limits.human_spoke(&channel);
+        }
+        let learn = match txt {
+            Some(ref txt) => {
+                nick != msg.author && !self.ignored.ignores(&src, &msg.channel, &msg.author, txt)
+            }
+            None => false,
+        };
+        if learn {
+            // the bot's nick would only make it talk about itself
+            let line = addressed.as_ref().or(txt.as_ref());
+            if let (Some(line), Some(dict)) = (line, self.dicts.get_mut(&path)) {
+                dict.learn_from_line(line);
+            }
+            if let (Some(line), Some(authors)) = (line, self.authors.as_mut()) {
+                authors.learn(&msg.author, line);
             }
         }
-        if rand::thread_rng().gen_range(0, 100) < self.probability {
-            let response = self.dict.generate_sentence();
-            core.send(
-                &src,
-                Message {
-                    author: "".to_owned(),
-                    channel: msg.channel,
-                    content: MessageContent::Text(response),
-                },
-            );
-            ResumeEventHandling::Resume
-        } else {
-            ResumeEventHandling::Resume
+        let now = Utc::now();
+        let speak = addressed.is_some()
+            || (self.rng.gen_range(0, 100) < self.probability
+                && self.limits.check(&channel, from_bot, now).is_ok());
+        if speak {
+            let seed = match (addressed.as_ref(), txt.as_ref()) {
+                (Some(rest), _) if !rest.is_empty() => Some(&rest[..]),
+                (Some(_), _) => None,
+                (None, Some(txt)) if self.context_replies => Some(&txt[..]),
+                (None, _) => None,
+            };
+            let (quality, sampling) = (self.quality, self.sampling);
+            let response = match self.dicts.get(&path) {
+                Some(dict) => dict.generate(seed, &quality, &sampling, &mut self.rng),
+                None => {
+                    self.report_errors(core);
+                    return ResumeEventHandling::Resume;
+                }
+            };
+            if let Some(response) = response {
+                let response = match addressed {
+                    Some(_) => format!("{}: {}", msg.author, response),
+                    None => response,
+                };
+                self.limits.sent(&channel, from_bot, now);
+                core.send(
+                    &src,
+                    Message {
+                        author: "".to_owned(),
+                        channel: msg.channel,
+                        content: MessageContent::Text(response),
+                    },
+                );
+            }
         }
+        self.report_errors(core);
+        ResumeEventHandling::Resume
     }
 
     fn handle_command(
@@ -108,41 +445,44 @@ impl RandomChat {
         src: SourceId,
         command: Command,
     ) -> ResumeEventHandling {
-        if command.params[0] == "gadaj" {
-            let response = self.dict.generate_sentence();
-            core.send(
-                &src,
-                Message {
-                    author: "".to_owned(),
-                    channel: command.channel,
-                    content: MessageContent::Text(response),
-                },
-            );
-            ResumeEventHandling::Stop
-        } else if command.params[0] == "random" {
-            if command.params.len() < 2 {
+        if command.param(0) == Some("gadaj") {
+            let path = self.dicts.path_for(&src, &command.channel);
+            let seed = command.rest(1);
+            let (quality, sampling) = (self.quality, self.sampling);
+            let response = match self.dicts.get(&path) {
+                Some(dict) => dict.generate(
+                    seed.as_ref().map(String::as_str),
+                    &quality,
+                    &sampling,
+                    &mut self.rng,
+                ),
+                None => {
+                    self.report_errors(core);
+                    return ResumeEventHandling::Stop;
+                }
+            };
+            if let Some(response) = response {
                 core.send(
                     &src,
                     Message {
                         author: "".to_owned(),
                         channel: command.channel,
-                        content: MessageContent::Text(format!("Not enough parameters")),
+                        content: MessageContent::Text(response),
                     },
                 );
-                return ResumeEventHandling::Stop;
             }
-            if command.params[1] == "enable" {
+            ResumeEventHandling::Stop
+        } else if command.param(0) == Some("imitate") && self.authors.is_some() {
+            let text = self.imitate(&command);
+            self.report_errors(core);
+            reply(core, &src, command.channel, text);
+            ResumeEventHandling::Stop
+        } else if command.param(0) == Some("random") {
+            // the dispatcher made sure it's one of the subcommands
+            let sub = command.param(1).unwrap_or_default().to_owned();
+            if sub == "enable" {
                 self.enabled = true;
-                let mut config = CONFIG.lock().ok().expect("Couldn't lock CONFIG");
-                config
-                    .modules
-                    .get_mut(&self.module_id)
-                    .expect(&format!("Couldn't find module {:?}", self.module_id))
-                    .config
-                    .as_mut()
-                    .map(|ref mut config| {
-                        config["enabled"] = Value::Boolean(true);
-                    });
+                self.store_config("enabled", Value::Boolean(true));
                 core.send(
                     &src,
                     Message {
@@ -152,18 +492,9 @@ impl RandomChat {
                     },
                 );
                 ResumeEventHandling::Stop
-            } else if command.params[1] == "disable" {
+            } else if sub == "disable" {
                 self.enabled = false;
-                let mut config = CONFIG.lock().ok().expect("Couldn't lock CONFIG");
-                config
-                    .modules
-                    .get_mut(&self.module_id)
-                    .expect(&format!("Couldn't find module {:?}", self.module_id))
-                    .config
-                    .as_mut()
-                    .map(|ref mut config| {
-                        config["enabled"] = Value::Boolean(false);
-                    });
+                self.store_config("enabled", Value::Boolean(false));
                 core.send(
                     &src,
                     Message {
@@ -173,6 +504,65 @@ impl RandomChat {
                     },
                 );
                 ResumeEventHandling::Stop
+            } else if sub == "forget" || sub == "forget-word" {
+                let text = self.forget(&src, &command);
+                self.report_errors(core);
+                reply(core, &src, command.channel, text);
+                ResumeEventHandling::Stop
+            } else if sub == "optout" || sub == "optin" {
+                let text = self.opt(&command.sender, sub == "optout");
+                reply(core, &src, command.channel, text);
+                ResumeEventHandling::Stop
+            } else if sub == "status" {
+                let status = self
+                    .limits
+                    .status(&channel_key(&src, &command.channel), Utc::now());
+                let text = format!(
+                    "RandomChat {}, probability {}%, temperature {}; {}",
+                    if self.enabled { "enabled" } else { "disabled" },
+                    self.probability,
+                    self.sampling.temperature,
+                    status
+                );
+                reply(core, &src, command.channel, text);
+                ResumeEventHandling::Stop
+            } else if sub == "stats" || sub == "about" {
+                let path = self.dicts.path_for(&src, &command.channel);
+                let messages = match (self.dicts.get(&path), command.param(2)) {
+                    (Some(dict), _) if sub == "stats" => {
+                        stats::stats(dict, fs::metadata(&path).ok().map(|file| file.len()))
+                    }
+                    (Some(dict), Some(word)) => stats::about(dict, word),
+                    (Some(_), None) => unreachable!(),
+                    (None, _) => vec![format!("The dictionary {} can't be used.", path)],
+                };
+                self.report_errors(core);
+                for text in messages {
+                    reply(core, &src, command.channel.clone(), text);
+                }
+                ResumeEventHandling::Stop
+            } else if sub == "temperature" {
+                let text = match command.parsed::<f64>(2) {
+                    None => format!("RandomChat temperature: {}", self.sampling.temperature),
+                    Some(Ok(temperature)) if is_valid_temperature(temperature) => {
+                        self.sampling.temperature = temperature;
+                        self.store_config("temperature", Value::Float(temperature));
+                        format!("RandomChat temperature set to {}.", temperature)
+                    }
+                    Some(_) => format!(
+                        "Invalid temperature: {} (expected a number not less than 0)",
+                        command.param(2).unwrap_or_default()
+                    ),
+                };
+                core.send(
+                    &src,
+                    Message {
+                        author: "".to_owned(),
+                        channel: command.channel,
+                        content: MessageContent::Text(text),
+                    },
+                );
+                ResumeEventHandling::Stop
             } else {
                 core.send(
                     &src,
@@ -180,7 +570,7 @@ impl RandomChat {
                         author: "".to_owned(),
                         channel: command.channel,
                         content: MessageContent::Text(
-                            format!("Unknown parameter value: {}", command.params[1]).to_string(),
+                            format!("Unknown parameter value: {}", sub).to_string(),
                         ),
                     },
                 );
@@ -191,9 +581,155 @@ impl RandomChat {
         }
     }
 
+    /// Handles `random forget <text>` and `random forget-word <word>`, returns the reply. Every
+    /// dictionary forgets it, the per-author ones too, so that nobody can be imitated saying it.
+    fn forget(&mut self, src: &SourceId, command: &Command) -> String {
+        if !self
+            .moderators
+            .iter()
+            .any(|moderator| moderator.is(src, &command.sender))
+        {
+            return "Only RandomChat moderators can do that.".to_owned();
+        }
+        let line = command.param(1) == Some("forget");
+        let text = if line {
+            // quoted or not, quotes that are part of the line have to be escaped
+            command.words(2).unwrap_or_default()
+        } else {
+            command.param(2).unwrap_or_default().to_owned()
+        };
+        let forget = |dict: &mut Dictionary| {
+            if line {
+                dict.forget_line(&text)
+            } else {
+                dict.forget_word(&text)
+            }
+        };
+        // wherever it was said, so that it doesn't have to be repeated where it was
+        let paths = self.dicts.known_paths();
+        let mut changed = self.dicts.update_each(paths, &forget);
+        if let Some(ref mut authors) = self.authors {
+            if authors.update(&forget) {
+                changed.push("the authors' dictionaries".to_owned());
+            }
+        }
+        let changed = changed.join(", ");
+        match (line, changed.is_empty()) {
+            (true, false) => format!("Forgotten from {}.", changed),
+            (true, true) => "I haven't learned that.".to_owned(),
+            (false, false) => format!("Forgot the word {} from {}.", text, changed),
+            (false, true) => format!("I don't know the word {}.", text),
+        }
+    }
+
+    /// Handles `imitate <nick>`, returns the reply
+    fn imitate(&mut self, command: &Command) -> String {
+        let nick = command.param(1).unwrap_or_default();
+        if self.ignored.is_opted_out(nick) {
+            return format!("{} has opted out.", nick);
+        }
+        let (quality, sampling) = (self.quality, self.sampling);
+        let authors = self.authors.as_mut().unwrap();
+        let sentence = match authors.get(nick) {
+            Some(dict) => dict.generate(None, &quality, &sampling, &mut self.rng),
+            None => None,
+        };
+        sentence.unwrap_or_else(|| format!("I don't know {} well enough yet.", nick))
+    }
+
+    /// Handles `random optout` and `random optin`, returns the reply
+    fn opt(&mut self, nick: &str, out: bool) -> String {
+        if self.ignored.is_opted_out(nick) == out {
+            return if out {
+                format!("{}: you have already opted out.", nick)
+            } else {
+                format!("{}: you haven't opted out.", nick)
+            };
+        }
+        match self.ignored.set_opted_out(nick, out) {
+            Ok(()) if out => {
+                if let Some(ref mut authors) = self.authors {
+                    if let Err(err) = authors.remove(nick) {
+                        eprintln!(
+                            "RandomChat: removing the dictionary of {} failed: {}",
+                            nick, err
+                        );
+                    }
+                }
+                format!("{}: I won't learn from your messages anymore.", nick)
+            }
+            Ok(()) => format!("{}: I'll learn from your messages again.", nick),
+            Err(err) => {
+                eprintln!("RandomChat: saving the opt-outs failed: {}", err);
+                format!(
+                    "{}: sorry, saving that failed, please try again later.",
+                    nick
+                )
+            }
+        }
+    }
+
+    fn save_dictionaries(&mut self, core: &mut CoreAPI) {
+        let mut results = self.dicts.save(self.backups);
+        if let Some(ref mut authors) = self.authors {
+            results.extend(authors.save());
+        }
+        for (path, result) in results {
+            match result {
+                Ok(()) => {
+                    if self.failing_saves.remove(&path) {
+                        self.notify_admins(
+                            core,
+                            format!("RandomChat: saving {} works again.", path),
+                        );
+                    }
+                }
+                Err(err) => {
+                    let text = format!(
+                        "RandomChat: saving the dictionary to {} failed: {}",
+                        path, err
+                    );
+                    eprintln!("{}", text);
+                    if self.failing_saves.insert(path) {
+                        self.notify_admins(core, text);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Logs and forwards to the admins the errors of loading the dictionaries
+    fn report_errors(&mut self, core: &mut CoreAPI) {
+        let mut errors = self.dicts.take_errors();
+        if let Some(ref mut authors) = self.authors {
+            errors.extend(authors.take_errors());
+        }
+        for err in errors {
+            let text = format!("RandomChat: {}", err);
+            eprintln!("{}", text);
+            self.notify_admins(core, text);
+        }
+    }
+
+    fn notify_admins(&self, core: &mut CoreAPI, text: String) {
+        for admin in &self.admins {
+            admin.send_text(core, text.clone());
+        }
+    }
+
     fn handle_timer(&mut self, core: &mut CoreAPI, id: String) -> ResumeEventHandling {
         if id == self.module_id {
-            let _ = self.dict.save(&self.dict_path);
+            if let Some(half_life) = self.decay_half_life {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|time| time.as_secs())
+                    .unwrap_or(0);
+                self.dicts.decay(now, half_life);
+                if let Some(ref mut authors) = self.authors {
+                    authors.decay(now, half_life);
+                }
+            }
+            self.save_dictionaries(core);
             self.init_timer(core);
             ResumeEventHandling::Stop
         } else {
@@ -201,3 +737,39 @@ impl RandomChat {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addressed(nick: &str, text: &str) -> Option<String> {
+        addressed_text(nick, &Channel::Channel("#chat".to_owned()), text)
+    }
+
+    #[test]
+    fn mentions_are_taken_out() {
+        let rest = Some("how are you?".to_owned());
+        assert_eq!(addressed("bot", "bot: how are you?"), rest);
+        assert_eq!(addressed("bot", "Bot, how are you?"), rest);
+        assert_eq!(addressed("bot", "how are you? @bot"), rest);
+        assert_eq!(addressed("1234", "<@1234> how are you?"), rest);
+        assert_eq!(addressed("1234", "<@!1234>, how are you?"), rest);
+        assert_eq!(addressed("bot", "how are you?"), None);
+        assert_eq!(addressed("bot", "robotics is fun, bots too"), None);
+        assert_eq!(addressed("bot", "<@5678> how are you?"), None);
+        assert_eq!(addressed("", "how are you?"), None);
+    }
+
+    #[test]
+    fn direct_messages_are_addressed() {
+        let user = Channel::User("someone".to_owned());
+        assert_eq!(
+            addressed_text("bot", &user, "how are you?"),
+            Some("how are you?".to_owned())
+        );
+        assert_eq!(
+            addressed_text("bot", &user, "bot: hi"),
+            Some("hi".to_owned())
+        );
+    }
+}
@@ -0,0 +1,194 @@
+//! Statistics of a dictionary, formatted for chat
+
+use super::dictionary::{Dictionary, Word};
+use std::collections::HashMap;
+
+/// The longest message sent, leaving room for the rest of an IRC line, which is limited to
+/// 512 bytes
+const MAX_MESSAGE_LEN: usize = 400;
+/// How many messages a list is split into at most, the rest of it is left out
+const MAX_MESSAGES: usize = 3;
+/// How many words are listed
+const TOP: usize = 10;
+
+/// The text cut to at most `max_len` bytes at a character boundary, with "…" at the end if it
+/// was cut
+fn truncate(text: &str, max_len: usize) -> String {
+    if text.len() <= max_len {
+        return text.to_owned();
+    }
+    let mut end = max_len.saturating_sub('…'.len_utf8());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &text[..end])
+}
+
+/// Joins the items with commas after `prefix`, starting a new message whenever one would get
+/// too long, up to `MAX_MESSAGES`. Items too long for a message of their own are cut short.
+/// `prefix` has to leave room for them.
+fn split_messages(prefix: &str, items: &[String]) -> Vec<String> {
+    let mut messages = Vec::new();
+    let mut message = prefix.to_owned();
+    let mut empty = true;
+    for item in items {
+        if !empty && message.len() + 2 + item.len() > MAX_MESSAGE_LEN {
+            messages.push(message);
+            if messages.len() == MAX_MESSAGES {
+                return messages;
+            }
+            message = String::new();
+            empty = true;
+        }
+        if !empty {
+            message.push_str(", ");
+        } else if !message.is_empty() {
+            message.push(' ');
+        }
+        let room = MAX_MESSAGE_LEN - message.len();
+        message.push_str(&truncate(item, room));
+        empty = false;
+    }
+    messages.push(message);
+    messages
+}
+
+/// Whether the word is worth listing, as opposed to punctuation and the sentinels
+fn is_listed(dict: &Dictionary, word: Word) -> bool {
+    match word {
+        Word::Word(_) => dict.word_str(word).chars().any(char::is_alphanumeric),
+        _ => false,
+    }
+}
+
+/// The `TOP` most common words, as "word (count)"
+fn most_common(dict: &Dictionary, counts: HashMap<Word, u64>) -> Vec<String> {
+    let mut counts: Vec<(Word, u64)> = counts
+        .into_iter()
+        .filter(|&(word, _)| is_listed(dict, word))
+        .collect();
+    // ties in a stable order
+    counts.sort_by(|a, b| {
+        b.1.cmp(&a.1)
+            .then_with(|| dict.word_str(a.0).cmp(dict.word_str(b.0)))
+    });
+    counts
+        .iter()
+        .take(TOP)
+        .map(|&(word, count)| format!("{} ({})", dict.word_str(word), count))
+        .collect()
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// `random stats`: the size of the dictionary and its most common words. `file_size` is the
+/// size of the saved dictionary, if it has been saved.
+pub fn stats(dict: &Dictionary, file_size: Option<u64>) -> Vec<String> {
+    let mut counts = HashMap::new();
+    dict.for_each_transition(|_, word, count| {
+        *counts.entry(word).or_insert(0) += count as u64;
+    });
+    let mut summary = format!(
+        "{} words, {} entries of {} words",
+        dict.num_words(),
+        dict.num_entries(),
+        dict.order()
+    );
+    if let Some(size) = file_size {
+        summary.push_str(&format!(", {} on disk", format_size(size)));
+    }
+    summary.push('.');
+    let mut messages = vec![summary];
+    let words = most_common(dict, counts);
+    if !words.is_empty() {
+        messages.extend(split_messages("Most common words:", &words));
+    }
+    messages
+}
+
+/// `random about <word>`: the most common words right before and right after the word
+pub fn about(dict: &Dictionary, word: &str) -> Vec<String> {
+    let word = match dict.find_word(word) {
+        Some(found @ Word::Word(_)) => found,
+        _ => return vec![format!("I don't know the word {}.", word)],
+    };
+    let (mut before, mut after) = (HashMap::new(), HashMap::new());
+    dict.for_each_transition(|entry, next, count| {
+        let last = entry[entry.len() - 1];
+        if last == word {
+            *after.entry(next).or_insert(0) += count as u64;
+        }
+        if next == word {
+            *before.entry(last).or_insert(0) += count as u64;
+        }
+    });
+    // leaving room for the words in the messages
+    let name = truncate(dict.word_str(word), MAX_MESSAGE_LEN / 2);
+    let mut messages = Vec::new();
+    for (counts, title, what) in vec![(before, "Before", "before"), (after, "After", "after")] {
+        let words = most_common(dict, counts);
+        if words.is_empty() {
+            messages.push(format!("Nothing comes {} {}.", what, name));
+        } else {
+            messages.extend(split_messages(&format!("{} {}:", title, name), &words));
+        }
+    }
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(item: &str, count: usize) -> Vec<String> {
+        vec![item.to_owned(); count]
+    }
+
+    #[test]
+    fn short_lists_are_one_message() {
+        assert_eq!(
+            split_messages("Words:", &["a (2)".to_owned(), "b (1)".to_owned()]),
+            vec!["Words: a (2), b (1)"]
+        );
+    }
+
+    #[test]
+    fn long_words_are_cut_short() {
+        let messages = split_messages("Words:", &items(&"x".repeat(1000), 2));
+        assert_eq!(messages.len(), 2);
+        for message in &messages {
+            assert_eq!(message.len(), MAX_MESSAGE_LEN);
+            assert!(message.ends_with("xx…"));
+        }
+        assert!(messages[0].starts_with("Words: xx"));
+        // two bytes a letter, the cut falls in the middle of one
+        let messages = split_messages("Word:", &items(&"ż".repeat(300), 1));
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].len(), MAX_MESSAGE_LEN - 1);
+        assert!(messages[0].ends_with("żż…"));
+    }
+
+    #[test]
+    fn long_lists_are_cut_off() {
+        let item = "y".repeat(150);
+        let messages = split_messages("Words:", &items(&item, 10));
+        assert_eq!(messages.len(), MAX_MESSAGES);
+        assert_eq!(messages[1], format!("{}, {}", item, item));
+        assert!(messages
+            .iter()
+            .all(|message| message.len() <= MAX_MESSAGE_LEN));
+    }
+}
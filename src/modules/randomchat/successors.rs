@@ -0,0 +1,185 @@
+fn lowbit(i: usize) -> usize {
+    i & i.wrapping_neg()
+}
+
+/// The words following an entry, with their counts. A word can be chosen with the probability
+/// proportional to its count in O(log n), and the counts can still be changed in O(log n).
+pub struct Successors {
+    /// the words in the order they were first added, with their counts
+    words: Vec<(u32, u32)>,
+    /// Fenwick tree of the counts: element `i` holds the sum of the counts of the words
+    /// `i + 1 - lowbit(i + 1) ..= i`
+    tree: Vec<u64>,
+    /// indices into `words`, sorted by the word
+    sorted: Vec<u32>,
+}
+
+impl Successors {
+    pub fn new() -> Successors {
+        Successors {
+            words: Vec::new(),
+            tree: Vec::new(),
+            sorted: Vec::new(),
+        }
+    }
+
+    /// Builds the successors from a list of words with their counts, sorted by the word
+    pub fn from_sorted(words: Vec<(u32, u32)>) -> Successors {
+        let mut tree: Vec<u64> = words.iter().map(|&(_, count)| count as u64).collect();
+        for i in 0..tree.len() {
+            let parent = i + lowbit(i + 1);
+            if parent < tree.len() {
+                tree[parent] += tree[i];
+            }
+        }
+        Successors {
+            sorted: (0..words.len() as u32).collect(),
+            words,
+            tree,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// The sum of the counts of the first `n` words
+    fn prefix(&self, mut n: usize) -> u64 {
+        let mut sum = 0;
+        while n > 0 {
+            sum += self.tree[n - 1];
+            n -= lowbit(n);
+        }
+        sum
+    }
+
+    pub fn total(&self) -> u64 {
+        self.prefix(self.words.len())
+    }
+
+    /// Adds `count` to the count of `word`
+    pub fn add(&mut self, word: u32, count: u32) {
+        let found = {
+            let words = &self.words;
+            self.sorted
+                .binary_search_by_key(&word, |&index| words[index as usize].0)
+        };
+        match found {
+            Ok(pos) => {
+                let index = self.sorted[pos] as usize;
+                let added = self.words[index].1.saturating_add(count) - self.words[index].1;
+                self.words[index].1 += added;
+                let mut n = index + 1;
+                while n <= self.tree.len() {
+                    self.tree[n - 1] += added as u64;
+                    n += lowbit(n);
+                }
+            }
+            Err(pos) => {
+                let index = self.words.len();
+                let n = index + 1;
+                // the new element covers itself and the elements n - lowbit(n) + 1 ..= n - 1
+                let covered = self.prefix(index) - self.prefix(n - lowbit(n));
+                self.words.push((word, count));
+                self.tree.push(count as u64 + covered);
+                self.sorted.insert(pos, index as u32);
+            }
+        }
+    }
+
+    /// The word at position `value` when the words are laid out one after another, each
+    /// taking as many positions as its count. `value` must be less than the total.
+    pub fn sample(&self, value: u64) -> u32 {
+        // find the longest prefix of the words with the sum of counts not exceeding `value`
+        let mut len = 0;
+        let mut remaining = value;
+        let mut step = (self.tree.len() + 1).next_power_of_two() / 2;
+        while step > 0 {
+            if len + step <= self.tree.len() && self.tree[len + step - 1] <= remaining {
+                len += step;
+                remaining -= self.tree[len - 1];
+            }
+            step /= 2;
+        }
+        self.words[len].0
+    }
+
+    /// The same as `sample`, but walking the words one by one. Only used for comparison in
+    /// benchmarks.
+    pub fn sample_linear(&self, mut value: u64) -> u32 {
+        for &(word, count) in &self.words {
+            if value < count as u64 {
+                return word;
+            }
+            value -= count as u64;
+        }
+        panic!("value out of range");
+    }
+
+    /// The words with their counts, in the order they were first added
+    pub fn iter(&self) -> ::std::slice::Iter<(u32, u32)> {
+        self.words.iter()
+    }
+
+    /// Replaces the contents of `out` with the words and their counts, sorted by the word
+    pub fn sorted_into(&self, out: &mut Vec<(u32, u32)>) {
+        out.clear();
+        out.extend(self.sorted.iter().map(|&index| self.words[index as usize]));
+    }
+
+    pub fn to_sorted(&self) -> Vec<(u32, u32)> {
+        let mut result = Vec::with_capacity(self.len());
+        self.sorted_into(&mut result);
+        result
+    }
+
+    /// Keeps only the words for which `f(word, count)` returns true
+    pub fn retain<F: FnMut(u32, u32) -> bool>(&mut self, mut f: F) {
+        let mut words = self.to_sorted();
+        words.retain(|&(word, count)| f(word, count));
+        if words.len() != self.len() {
+            *self = Successors::from_sorted(words);
+        }
+    }
+
+    /// Changes the words with `f`, which has to keep them in the same order
+    pub fn map_words<F: Fn(u32) -> u32>(&mut self, f: F) {
+        for word in &mut self.words {
+            word.0 = f(word.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_samples_like_linear(successors: &Successors) {
+        for value in 0..successors.total() {
+            assert_eq!(
+                successors.sample(value),
+                successors.sample_linear(value),
+                "value {}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn sample_agrees_with_the_linear_walk() {
+        let mut successors = Successors::new();
+        for i in 0..37 {
+            // added out of order, some of them more than once
+            let word = (i * 11) % 23;
+            successors.add(word, (i % 5) + 1);
+            assert_samples_like_linear(&successors);
+        }
+        let rebuilt = Successors::from_sorted(successors.to_sorted());
+        assert_eq!(rebuilt.total(), successors.total());
+        assert_samples_like_linear(&rebuilt);
+    }
+}
@@ -0,0 +1,234 @@
+use regex::Regex;
+
+/// Splits lines into the words learned by a `Dictionary`, and joins generated words back
+pub trait Tokenizer {
+    /// Returns `None` if the line shouldn't be learned at all
+    fn tokenize(&self, line: &str) -> Option<Vec<String>>;
+    fn join(&self, tokens: &[&str]) -> String;
+}
+
+/// Returns the tokenizer with the given name, as used in the configs
+pub fn from_name(name: &str) -> Option<Box<Tokenizer + Send>> {
+    match name {
+        "whitespace" => Some(Box::new(WhitespaceTokenizer)),
+        "chat" => Some(Box::new(ChatTokenizer)),
+        _ => None,
+    }
+}
+
+/// Words are whatever is between whitespace, punctuation included
+pub struct WhitespaceTokenizer;
+
+impl Tokenizer for WhitespaceTokenizer {
+    fn tokenize(&self, line: &str) -> Option<Vec<String>> {
+        Some(line.split_whitespace().map(str::to_owned).collect())
+    }
+
+    fn join(&self, tokens: &[&str]) -> String {
+        tokens.join(" ")
+    }
+}
+
+lazy_static! {
+    static ref URL: Regex = Regex::new(r"^[(<]?(?i:https?://|ftp://|www\.)").unwrap();
+    /// Discord and Slack mentions (<@1234>, <#channel>, <!here>) and @nick
+    static ref MENTION: Regex = Regex::new(r"^(<[@#!][^>]*>|@\w)").unwrap();
+    static ref EMOTICON: Regex = Regex::new(r"^[:;=8xX][-']?[()\[\]DPpOo3/\\|*]$").unwrap();
+    /// a line of a stack trace: "  at foo.Bar(Bar.java:12)", "  File "x.py", line 3, in f"
+    static ref STACK_TRACE: Regex =
+        Regex::new(r#"^\s*(at \S+\(.*\)|File ".*", line \d+|Traceback \(most recent call last\))"#)
+            .unwrap();
+}
+
+const OPENING: &[char] = &['(', '[', '{'];
+const CLOSING: &[char] = &['.', ',', '!', '?', ';', ':', '…', ')', ']', '}'];
+/// Split off the beginning and the end of words, each into a token of its own, so that a quoted
+/// word is the same word; apostrophes inside words stay
+const QUOTES: &[char] = &['"', '\''];
+/// Symbols common in code, but rare in chat
+const CODE_SYMBOLS: &[char] = &[
+    '{', '}', '(', ')', '[', ']', ';', '=', '<', '>', '$', '\\', '|', '&',
+];
+
+/// Separates punctuation from words, drops URLs and mentions and skips lines that look like code
+pub struct ChatTokenizer;
+
+impl ChatTokenizer {
+    fn looks_like_code(line: &str) -> bool {
+        if line.contains("```") || line.starts_with("    ") || line.starts_with('\t') {
+            return true;
+        }
+        if STACK_TRACE.is_match(line) || line.trim_end().ends_with(&[';', '{', '}'][..]) {
+            return true;
+        }
+        let (mut symbols, mut total) = (0, 0);
+        for word in line.split_whitespace() {
+            total += word.chars().count();
+            if EMOTICON.is_match(word) {
+                continue;
+            }
+            // brackets around words, as in "(see above)", are prose
+            let word = if word.chars().any(char::is_alphanumeric) {
+                word.trim_start_matches(OPENING).trim_end_matches(CLOSING)
+            } else {
+                word
+            };
+            symbols += word.chars().filter(|c| CODE_SYMBOLS.contains(c)).count();
+        }
+        total >= 8 && symbols * 6 > total
+    }
+
+    /// Splits opening brackets and quotes from the beginning of the word, and punctuation and
+    /// quotes from its end
+    fn split_punctuation(word: &str, tokens: &mut Vec<String>) {
+        // emoticons, "...", "-" etc.
+        if !word.chars().any(char::is_alphanumeric) {
+            tokens.push(word.to_owned());
+            return;
+        }
+        // the quotes are ASCII, and the word doesn't run out, as its alphanumerics stay
+        let mut core = word;
+        loop {
+            let trimmed = if core.starts_with(QUOTES) {
+                &core[1..]
+            } else {
+                core.trim_start_matches(OPENING)
+            };
+            if trimmed.len() == core.len() {
+                break;
+            }
+            tokens.push(core[..core.len() - trimmed.len()].to_owned());
+            core = trimmed;
+        }
+        let mut trailing = Vec::new();
+        loop {
+            let trimmed = if core.ends_with(QUOTES) {
+                &core[..core.len() - 1]
+            } else {
+                core.trim_end_matches(CLOSING)
+            };
+            if trimmed.len() == core.len() {
+                break;
+            }
+            trailing.push(core[trimmed.len()..].to_owned());
+            core = trimmed;
+        }
+        tokens.push(core.to_owned());
+        tokens.extend(trailing.into_iter().rev());
+    }
+
+    fn attaches_left(token: &str) -> bool {
+        token.chars().all(|c| CLOSING.contains(&c)) && !EMOTICON.is_match(token)
+    }
+
+    fn attaches_right(token: &str) -> bool {
+        token.chars().all(|c| OPENING.contains(&c))
+    }
+
+    /// The index of the quote in `QUOTES`. Each of them opens and closes quotations alternately.
+    fn quote(token: &str) -> Option<usize> {
+        let mut chars = token.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => QUOTES.iter().position(|&quote| quote == c),
+            _ => None,
+        }
+    }
+}
+
+impl Tokenizer for ChatTokenizer {
+    fn tokenize(&self, line: &str) -> Option<Vec<String>> {
+        if ChatTokenizer::looks_like_code(line) {
+            return None;
+        }
+        let mut tokens = Vec::new();
+        for word in line.split_whitespace() {
+            // these would only make generated sentences ping people or advertise random links
+            if URL.is_match(word) || MENTION.is_match(word) {
+                // keep the punctuation following them, so that the rest still makes sense
+                let trimmed = word.trim_end_matches(CLOSING);
+                if trimmed.len() < word.len() && !tokens.is_empty() {
+                    tokens.push(word[trimmed.len()..].to_owned());
+                }
+                continue;
+            }
+            ChatTokenizer::split_punctuation(word, &mut tokens);
+        }
+        Some(tokens)
+    }
+
+    fn join(&self, tokens: &[&str]) -> String {
+        let mut result = String::new();
+        let mut previous_opening = true;
+        let mut quoted = [false; 2];
+        for token in tokens {
+            let (attaches_left, attaches_right) = if let Some(quote) = ChatTokenizer::quote(token) {
+                quoted[quote] = !quoted[quote];
+                (!quoted[quote], quoted[quote])
+            } else {
+                (
+                    ChatTokenizer::attaches_left(token),
+                    ChatTokenizer::attaches_right(token),
+                )
+            };
+            if !previous_opening && !attaches_left {
+                result.push(' ');
+            }
+            result.push_str(token);
+            previous_opening = attaches_right;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(line: &str) -> Option<Vec<String>> {
+        ChatTokenizer.tokenize(line)
+    }
+
+    #[test]
+    fn emoticons_and_brackets_are_not_code() {
+        for line in &["ok (see above) :)", "haha :) :)", "I'm fine (really) ;)"] {
+            assert!(tokens(line).is_some(), "{}", line);
+        }
+        for line in &[
+            "fn main() { println!(\"hi\"); }",
+            "if (a[i] == b[j]) x = y",
+            "let x = foo(bar);",
+            "```rust",
+        ] {
+            assert_eq!(tokens(line), None, "{}", line);
+        }
+    }
+
+    #[test]
+    fn splits_and_joins_punctuation() {
+        let line = "ok (see above), fine... :)";
+        let tokens = tokens(line).unwrap();
+        assert_eq!(
+            tokens,
+            vec!["ok", "(", "see", "above", "),", "fine", "...", ":)"]
+        );
+        let tokens: Vec<&str> = tokens.iter().map(String::as_str).collect();
+        assert_eq!(ChatTokenizer.join(&tokens), line);
+    }
+
+    #[test]
+    fn splits_and_joins_quotes() {
+        let line = "he said \"hi, it's me\" and 'bye'.";
+        let tokens = tokens(line).unwrap();
+        assert_eq!(
+            tokens,
+            vec!["he", "said", "\"", "hi", ",", "it's", "me", "\"", "and", "'", "bye", "'", ".",]
+        );
+        let tokens: Vec<&str> = tokens.iter().map(String::as_str).collect();
+        assert_eq!(ChatTokenizer.join(&tokens), line);
+        // the same words as without the quotes
+        assert_eq!(
+            ChatTokenizer.tokenize("\"hello,\"").unwrap(),
+            vec!["\"", "hello", ",", "\""]
+        );
+    }
+}
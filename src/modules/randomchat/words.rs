@@ -0,0 +1,95 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// Hash of the lowercased word, computed without allocating the lowercased string
+fn lowercase_hash(word: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for c in word.chars().flat_map(char::to_lowercase) {
+        c.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Whether the words are equal when lowercased
+pub fn eq_ignore_case(a: &str, b: &str) -> bool {
+    a.chars()
+        .flat_map(char::to_lowercase)
+        .eq(b.chars().flat_map(char::to_lowercase))
+}
+
+/// Compares the lowercased words
+pub fn cmp_ignore_case(a: &str, b: &str) -> ::std::cmp::Ordering {
+    a.chars()
+        .flat_map(char::to_lowercase)
+        .cmp(b.chars().flat_map(char::to_lowercase))
+}
+
+/// The words of a dictionary, stored back to back in a single string and looked up
+/// case-insensitively by the hash of their lowercased form, so that every word is stored once
+/// and without a separate allocation
+pub struct WordTable {
+    arena: String,
+    /// where each word starts in `arena`, followed by the end of the last word
+    offsets: Vec<u32>,
+    /// hash of the lowercased word -> the index of the word
+    index: HashMap<u64, u32>,
+    /// words whose hash is already taken by a different word in `index`
+    collisions: HashMap<u64, Vec<u32>>,
+}
+
+impl WordTable {
+    pub fn new() -> WordTable {
+        WordTable {
+            arena: String::new(),
+            offsets: vec![0],
+            index: HashMap::new(),
+            collisions: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    pub fn get(&self, index: u32) -> &str {
+        let index = index as usize;
+        &self.arena[self.offsets[index] as usize..self.offsets[index + 1] as usize]
+    }
+
+    /// Looks up a word, case-insensitively
+    pub fn find(&self, word: &str) -> Option<u32> {
+        let hash = lowercase_hash(word);
+        match self.index.get(&hash) {
+            Some(&index) if eq_ignore_case(self.get(index), word) => Some(index),
+            Some(_) => self.collisions.get(&hash).and_then(|indices| {
+                indices
+                    .iter()
+                    .cloned()
+                    .find(|&index| eq_ignore_case(self.get(index), word))
+            }),
+            None => None,
+        }
+    }
+
+    /// Adds a word without checking whether it is already known. Lookups return the first of
+    /// the words differing only in case.
+    pub fn push(&mut self, word: &str) -> u32 {
+        let index = self.len() as u32;
+        self.arena.push_str(word);
+        self.offsets.push(self.arena.len() as u32);
+        let hash = lowercase_hash(word);
+        match self.index.get(&hash) {
+            Some(&other) if eq_ignore_case(self.get(other), word) => (),
+            Some(_) => self
+                .collisions
+                .entry(hash)
+                .or_insert_with(Vec::new)
+                .push(index),
+            None => {
+                self.index.insert(hash, index);
+            }
+        }
+        index
+    }
+}